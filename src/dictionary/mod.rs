@@ -1,38 +1,495 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
-use crate::settings::HarlawSettings;
+use crate::settings::{HarlawSettings, get_no_markup_settings};
+use crate::HarlawError;
 use crate::formatter;
 use crate::reader;
 use crate::writer;
 
+// Maximum nesting of #INCLUDE directives.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// Abbreviation dictionary of foo.dsl is foo_abrv.dsl, possibly compressed.
+const ABBREVIATIONS_SUFFIX: &str = "_abrv";
+const DSL_EXTENSIONS: &[&str] = &[".dsl.dz", ".dsl.gz", ".dsl"];
+
+// Annotation and icon of foo.dsl are foo.ann and foo.bmp.
+const ANNOTATION_EXTENSION: &str = ".ann";
+const ICON_EXTENSIONS: &[&str] = &[".bmp", ".png", ".ico"];
+
 /// Individual dictionary entry.
 /// Each entry contains word, and at least one definition for the word.
+/// Word is the headword as displayed, while `keys` are its expanded lookup forms:
+/// `abbindi(s)` is looked up with `abbindi` and `abbindis`, `{to }go` with `go`.
 ///
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct DictionaryEntry {
     pub word: String,
-    pub definitions: Vec<String>
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+    pub definitions: Vec<Definition>,
+    /// Headwords referenced from the definitions with `[ref]` tags or `<<word>>` shorthands.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<String>,
+    /// Translations from `[trn]` and `[!trs]` zones, when zones are extracted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub translations: Vec<String>,
+    /// Examples from `[ex]` zones, when zones are extracted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<String>,
+    /// Comments from `[com]` zones, when zones are extracted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<String>,
+    /// Grammatical labels from `[p]` tags, with expansions from the abbreviation dictionary.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<Label>,
+    /// Sound, image and other media files referenced with `[s]` tags.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub media: Vec<MediaRef>,
+    /// Editor comments from `{{...}}` blocks, when they are kept.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// Phrase subentries introduced with `@` lines within the card.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subentries: Vec<DictionaryEntry>,
+}
+
+/// Definition of entry, with the margin level of its `[mN]` tag.
+/// Lingvo uses margins to indent sub-senses and examples. Level is 0 when not set.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Definition {
+    pub level: u8,
+    pub text: String,
+}
+
+/// Grammatical label of entry, eg. "f." with expansion "feminine".
+/// Expansion is not set when the abbreviation dictionary does not have the label.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Label {
+    pub abbreviation: String,
+    pub expansion: Option<String>,
+}
+
+/// Kind of media file, by its extension.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum MediaKind {
+    Sound,
+    Image,
+    Video,
+    Other,
+}
+
+/// Where media file was found, when resolved against the resources of the dictionary.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum MediaLocation {
+    /// File in the resource directory next to dictionary.
+    File(String),
+    /// File inside the resource zip archive next to dictionary.
+    Archive { archive: String, file: String },
+}
+
+/// Media file referenced from entry, eg. `[s]hus.wav[/s]`.
+/// Location is set only when media is resolved, and the file is found.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MediaRef {
+    pub file: String,
+    pub kind: MediaKind,
+    pub location: Option<MediaLocation>,
+}
+
+/// Dictionary header information, read from the #-prefixed lines of DSL file.
+/// Headers without a dedicated field are kept in `extra`, keyed by header name.
+///
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct DictionaryMetadata {
+    pub name: Option<String>,
+    pub index_language: Option<String>,
+    pub contents_language: Option<String>,
+    pub source_code_page: Option<String>,
+    pub extra: BTreeMap<String, String>,
+    /// Description and copyright from the `.ann` annotation file next to dictionary.
+    #[serde(default)]
+    pub annotation: Option<String>,
+    /// Path of the dictionary icon next to dictionary, eg. `my-dictionary.bmp`.
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+/// Dictionary entries along with dictionary metadata.
+///
+#[derive(Serialize, Deserialize)]
+pub struct Dictionary {
+    pub metadata: DictionaryMetadata,
+    pub entries: Vec<DictionaryEntry>,
+}
+
+/// Kind of problem found while reading DSL file in lenient mode.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum DiagnosticCategory {
+    /// Line contained characters that could not be decoded. They were replaced with U+FFFD.
+    Encoding,
+    /// Definition line without any preceding headword. The line was skipped.
+    OrphanDefinition,
+    /// Headword without any definition lines. The headword was skipped.
+    MissingDefinition,
+    /// `{{` comment without closing `}}`. The rest of the file after it was taken as comment.
+    UnterminatedComment,
+    /// `#INCLUDE` directive whose file could not be read. The directive was skipped.
+    UnresolvedInclude,
+}
+
+/// Problem found while reading DSL file in lenient mode.
+/// Contains the line number (starting from 1) and the offending text.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub text: String,
+    pub category: DiagnosticCategory,
+}
+
+/// Result of lenient parse: all entries that could be read,
+/// and warnings about the parts that could not.
+///
+#[derive(Serialize, Deserialize)]
+pub struct ParseReport {
+    pub entries: Vec<DictionaryEntry>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Reference to a headword that is not found in the dictionary.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct BrokenReference {
+    /// Word of the entry containing the reference.
+    pub word: String,
+    pub reference: String,
+}
+
+fn lookup_form(word: &str) -> String {
+    word.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase()
+}
+
+// Entries along with their subentries, which can be looked up as well.
+fn with_subentries(entries: &[DictionaryEntry]) -> Vec<&DictionaryEntry> {
+    entries
+        .iter()
+        .flat_map(|entry| std::iter::once(entry).chain(with_subentries(&entry.subentries)))
+        .collect()
+}
+
+// References are matched case-insensitively against words and lookup keys of entries.
+pub fn find_broken_references(entries: &[DictionaryEntry]) -> Vec<BrokenReference> {
+    let entries = with_subentries(entries);
+    let headwords: HashSet<String> = entries
+        .iter()
+        .flat_map(|entry| entry.keys.iter().chain(std::iter::once(&entry.word)))
+        .map(|word| lookup_form(word))
+        .collect();
+
+    entries
+        .iter()
+        .flat_map(|entry| {
+            entry.references
+                .iter()
+                .filter(|reference| !headwords.contains(&lookup_form(reference)))
+                .map(move |reference| BrokenReference {
+                    word: entry.word.clone(),
+                    reference: reference.clone(),
+                })
+        })
+        .collect()
+}
+
+fn read_included_entries(filepath: &Path, settings: &HarlawSettings, include_chain: &mut Vec<PathBuf>) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    let path = filepath.to_string_lossy().to_string();
+
+    if !reader::is_dsl_file(&path) {
+        return Err(HarlawError::NotDsl);
+    }
+
+    let lines = reader::read_dsl_file(&path, settings.encoding)?;
+
+    format_included_lines(filepath, lines, settings, include_chain)
+}
+
+// Lines of a file in the include chain. The same file may not appear twice in the chain.
+fn format_included_lines(filepath: &Path, lines: Vec<String>, settings: &HarlawSettings, include_chain: &mut Vec<PathBuf>) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    let path = filepath.to_string_lossy().to_string();
+    let canonical_path = fs::canonicalize(filepath)?;
+
+    if include_chain.contains(&canonical_path) {
+        return Err(HarlawError::IncludeCycle { path });
+    }
+
+    if include_chain.len() > MAX_INCLUDE_DEPTH {
+        return Err(HarlawError::IncludeTooDeep { path });
+    }
+
+    include_chain.push(canonical_path);
+    let entries = format_with_includes(filepath, lines, settings, include_chain);
+    include_chain.pop();
+
+    entries
+}
+
+// Included files are resolved relative to the including file.
+fn format_with_includes(filepath: &Path, lines: Vec<String>, settings: &HarlawSettings, include_chain: &mut Vec<PathBuf>) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    let directory = filepath.parent().unwrap_or_else(|| Path::new(""));
+
+    formatter::format_entries_with_includes(lines, settings.clone(), |include| {
+        let included_path = directory.join(include);
+
+        read_included_entries(&included_path, settings, include_chain).map_err(|error| match error {
+            HarlawError::IncludeCycle { .. } | HarlawError::IncludeTooDeep { .. } | HarlawError::Include { .. } => error,
+            error => HarlawError::Include {
+                path: included_path.to_string_lossy().to_string(),
+                error: Box::new(error),
+            },
+        })
+    })
+}
+
+// Entries of already read DSL file, along with the files it includes.
+fn format_dictionary_lines(filepath: &str, lines: Vec<String>, settings: &HarlawSettings) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    let mut include_chain = vec![];
+
+    format_included_lines(Path::new(filepath), lines, settings, &mut include_chain)
+}
+
+// foo.dsl.dz -> foo, shared by the companion files of the dictionary.
+pub(crate) fn dictionary_stem(filepath: &str) -> Option<&str> {
+    DSL_EXTENSIONS
+        .iter()
+        .find_map(|extension| filepath.strip_suffix(extension))
+}
+
+fn abbreviations_path(filepath: &str) -> Option<PathBuf> {
+    let stem = dictionary_stem(filepath)?;
+
+    if stem.ends_with(ABBREVIATIONS_SUFFIX) {
+        return None;
+    }
+
+    DSL_EXTENSIONS
+        .iter()
+        .map(|extension| PathBuf::from(format!("{}{}{}", stem, ABBREVIATIONS_SUFFIX, extension)))
+        .find(|path| path.is_file())
+}
+
+// Each headword of the abbreviation dictionary is expanded with its first definition, as plain text.
+fn read_abbreviations(filepath: &str, encoding: Option<&str>) -> Result<BTreeMap<String, String>, HarlawError> {
+    let lines = reader::read_dsl_file(filepath, encoding)?;
+    let entries = formatter::format_entries(lines, get_no_markup_settings())?;
+
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| {
+            let expansion = entry.definitions.into_iter().next()?.text;
+            Some((entry.word, expansion))
+        })
+        .collect())
+}
+
+/*
+ * Loads abbreviations from the abbreviation dictionary next to DSL file,
+ * unless settings already have abbreviations. Abbreviation dictionary is optional.
+ */
+pub fn with_abbreviations<'a>(filepath: &str, mut settings: HarlawSettings<'a>) -> Result<HarlawSettings<'a>, HarlawError> {
+    if !settings.abbreviations.is_empty() {
+        return Ok(settings);
+    }
+
+    if let Some(path) = abbreviations_path(filepath) {
+        let path = path.to_string_lossy().to_string();
+
+        settings.abbreviations = read_abbreviations(&path, settings.encoding).map_err(|error| HarlawError::Abbreviations {
+            path,
+            error: Box::new(error),
+        })?;
+    }
+
+    Ok(settings)
+}
+
+fn companion_path(filepath: &str, extensions: &[&str]) -> Option<PathBuf> {
+    let stem = dictionary_stem(filepath)?;
+
+    extensions
+        .iter()
+        .map(|extension| PathBuf::from(format!("{}{}", stem, extension)))
+        .find(|path| path.is_file())
+}
+
+// Annotation is plain text, so characters that can not be decoded are replaced rather than failing.
+fn read_annotation(path: &Path, encoding: Option<&str>) -> Result<Option<String>, HarlawError> {
+    let decoded = reader::decode_bytes_lossy(fs::read(path)?, encoding)?;
+    let annotation = decoded.lines.join("\n").trim().to_string();
+
+    Ok(Some(annotation).filter(|annotation| !annotation.is_empty()))
+}
+
+/*
+ * Metadata from DSL headers, along with the annotation and icon files next to dictionary.
+ * Both of them are optional.
+ */
+pub fn read_metadata(filepath: &str, settings: &HarlawSettings) -> Result<DictionaryMetadata, HarlawError> {
+    let headers = reader::read_dsl_headers(filepath, settings.encoding)?;
+
+    with_companion_files(filepath, formatter::format_metadata(&headers), settings)
+}
+
+// Annotation is read with the encoding of settings, like the dictionary itself.
+fn with_companion_files(filepath: &str, mut metadata: DictionaryMetadata, settings: &HarlawSettings) -> Result<DictionaryMetadata, HarlawError> {
+    if let Some(path) = companion_path(filepath, &[ANNOTATION_EXTENSION]) {
+        metadata.annotation = read_annotation(&path, settings.encoding)?;
+    }
+
+    metadata.icon = companion_path(filepath, ICON_EXTENSIONS).map(|path| path.to_string_lossy().to_string());
+
+    Ok(metadata)
+}
+
+pub fn to_dictionary(filepath: &str, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    let settings = with_abbreviations(filepath, settings)?;
+    let lines = reader::read_dsl_file(filepath, settings.encoding)?;
+
+    format_dictionary_lines(filepath, lines, &settings)
+}
+
+pub fn from_str(content: &str, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    let lines = reader::read_dsl_str(content);
+
+    formatter::format_entries(lines, settings)
+}
+
+pub fn from_reader<R: Read>(source: R, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    let lines = reader::read_dsl_source(source, settings.encoding)?;
+
+    formatter::format_entries(lines, settings)
+}
+
+// Lenient version of to_dictionary: broken entries are skipped and reported instead of failing.
+pub fn to_dictionary_lenient(filepath: &str, settings: HarlawSettings) -> Result<ParseReport, HarlawError> {
+    let settings = with_abbreviations(filepath, settings)?;
+    let decoded = reader::read_dsl_file_lossy(filepath, settings.encoding)?;
+
+    let mut warnings: Vec<Diagnostic> = decoded.broken_lines
+        .iter()
+        .map(|line| Diagnostic {
+            line: *line,
+            text: decoded.lines.get(line - 1).cloned().unwrap_or_default(),
+            category: DiagnosticCategory::Encoding,
+        })
+        .collect();
+
+    let directory = Path::new(filepath).parent().unwrap_or_else(|| Path::new(""));
+    let mut include_chain = vec![fs::canonicalize(filepath)?];
+    let (entries, format_warnings) = formatter::format_entries_leniently(decoded.lines, settings.clone(), |include| {
+        read_included_entries(&directory.join(include), &settings, &mut include_chain).ok()
+    });
+
+    warnings.extend(format_warnings);
+    warnings.sort_by_key(|warning| warning.line);
+
+    Ok(ParseReport { entries, warnings })
 }
 
-pub fn to_dictionary(filepath: &str, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, &'static str> {
-    let lines = reader::read_dsl_file(filepath)?;
+// DSL file is read only once, for both headers and entries.
+pub fn to_dictionary_with_metadata(filepath: &str, settings: HarlawSettings) -> Result<Dictionary, HarlawError> {
+    let settings = with_abbreviations(filepath, settings)?;
+    let lines = reader::read_dsl_file(filepath, settings.encoding)?;
+    let metadata = with_companion_files(filepath, formatter::format_metadata(&lines), &settings)?;
 
-    Ok(formatter::format_entries(lines, settings))
+    Ok(Dictionary {
+        metadata,
+        entries: format_dictionary_lines(filepath, lines, &settings)?,
+    })
 }
 
-pub fn to_json(input: &str, output: &str, settings: HarlawSettings) -> Result<(), &'static str> {
+pub fn to_json(input: &str, output: &str, settings: HarlawSettings) -> Result<(), HarlawError> {
     let dictionary = to_dictionary(input, settings)?;
 
     writer::write_entries_to_json(output, &dictionary)
 }
 
+pub fn to_json_with_metadata(input: &str, output: &str, settings: HarlawSettings) -> Result<(), HarlawError> {
+    let dictionary = to_dictionary_with_metadata(input, settings)?;
+
+    writer::write_dictionary_to_json(output, &dictionary)
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
-    use std::path::Path;
     use crate::settings::get_default_settings;
 
+    #[test]
+    fn finds_broken_references() {
+        let content = "foo\n\t[m1]See [ref]Bar[/ref] and [ref]missing[/ref].[/m]\nbar(s)\n\t[m1]See <<foo>> and <<bars>>.[/m]";
+        let entries = from_str(content, get_default_settings()).unwrap();
+
+        let result = find_broken_references(&entries);
+
+        assert_eq!(result, vec![BrokenReference {
+            word: String::from("foo"),
+            reference: String::from("missing"),
+        }]);
+    }
+
+    #[test]
+    fn finds_references_to_subentries() {
+        let content = "go\n\t[m1]to move[/m]\n\t@ go away\n\t[m1]See [ref]leave[/ref].[/m]\nleave\n\t[m1]See <<go away>>.[/m]";
+        let entries = from_str(content, get_default_settings()).unwrap();
+
+        assert!(find_broken_references(&entries).is_empty());
+    }
+
+    #[test]
+    fn reads_annotation_and_icon_next_to_dictionary() {
+        let filename = "./src/dictionary/fixtures/labels/labels.dsl";
+
+        let result = read_metadata(filename, &get_default_settings()).unwrap();
+
+        assert_eq!(result.name, Some(String::from("Labelled dictionary")));
+        assert_eq!(result.annotation, Some(String::from("Excerpt of A Concise Dictionary of Old Icelandic.\n\nGeir T. Zoëga, 1910. Public domain.")));
+        assert_eq!(result.icon, Some(String::from("./src/dictionary/fixtures/labels/labels.bmp")));
+    }
+
+    #[test]
+    fn reads_annotation_with_encoding_of_settings() {
+        let filename = "./src/dictionary/fixtures/cyrillic/cyrillic.dsl";
+        let settings = HarlawSettings {
+            encoding: Some("windows-1251"),
+            ..get_default_settings()
+        };
+
+        let result = read_metadata(filename, &settings).unwrap();
+
+        assert_eq!(result.name, Some(String::from("Русский словарь")));
+        assert_eq!(result.annotation, Some(String::from("Словарь для проверки.")));
+    }
+
+    #[test]
+    fn reads_metadata_without_annotation_or_icon() {
+        let filename = "./src/dictionary/fixtures/zoega-excerpt.dsl";
+
+        let result = read_metadata(filename, &get_default_settings()).unwrap();
+
+        assert_eq!(result.annotation, None);
+        assert_eq!(result.icon, None);
+    }
+
     #[test]
     fn errors_if_not_readable_dictionary() {
         let filename = "./imaginary-dictionary.dsl";
@@ -50,11 +507,180 @@ mod tests {
     
     
         assert_eq!(result[0].word, "a");
-        assert_eq!(result[0].definitions[0], "a negative suffix to verbs, <i>not</i>;");
-        assert_eq!(result[0].definitions[1], "era útmakligt, <i>at it is not unmeet that</i>.");
+        assert_eq!(result[0].definitions[0].text, "a negative suffix to verbs, <i>not</i>;");
+        assert_eq!(result[0].definitions[1].text, "era útmakligt, <i>at it is not unmeet that</i>.");
 
         assert_eq!(result[1].word, "abbadis");
-        assert_eq!(result[1].definitions[0], "(pl. -ar), f. <i>abbess</i>.");
+        assert_eq!(result[1].definitions[0].text, "(pl. -ar), f. <i>abbess</i>.");
+    }
+
+    #[test]
+    fn gets_dictionary_with_included_files() {
+        let filename = "./src/dictionary/fixtures/includes/main.dsl";
+
+        let result = to_dictionary(filename, get_default_settings()).unwrap();
+
+        let words: Vec<&str> = result.iter().map(|entry| entry.word.as_str()).collect();
+        assert_eq!(words, vec!["a", "abbadis", "abbast", "aðal"]);
+        assert_eq!(result[1].definitions[0].text, "(pl. -ar), f. <i>abbess</i>.");
+    }
+
+    #[test]
+    fn gets_dictionary_leniently_with_included_files() {
+        let filename = "./src/dictionary/fixtures/includes/main.dsl";
+
+        let result = to_dictionary_lenient(filename, get_default_settings()).unwrap();
+
+        let words: Vec<&str> = result.entries.iter().map(|entry| entry.word.as_str()).collect();
+        assert_eq!(words, vec!["a", "abbadis", "abbast", "aðal"]);
+        assert!(result.warnings.is_empty());
+    }
+
+    #[test]
+    fn reports_unresolved_include_leniently() {
+        let filename = "./src/dictionary/fixtures/includes/broken-include.dsl";
+
+        let result = to_dictionary_lenient(filename, get_default_settings()).unwrap();
+
+        assert_eq!(result.entries[0].word, "foo");
+        assert_eq!(result.warnings, vec![
+            Diagnostic { line: 1, text: String::from("#INCLUDE \"missing.dsl\""), category: DiagnosticCategory::UnresolvedInclude },
+        ]);
+    }
+
+    #[test]
+    fn strips_whole_extension_from_dictionary_stem() {
+        assert_eq!(dictionary_stem("foo.dsl"), Some("foo"));
+        assert_eq!(dictionary_stem("foo.dsl.dz"), Some("foo"));
+        assert_eq!(dictionary_stem("foo.dsl.gz"), Some("foo"));
+        assert_eq!(dictionary_stem("foo.gz"), None);
+    }
+
+    #[test]
+    fn loads_abbreviation_dictionary_next_to_file() {
+        let filename = "./src/dictionary/fixtures/labels/labels.dsl";
+
+        let result = to_dictionary(filename, get_default_settings()).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].definitions[0].text, "(að), <span title=\"reflexive verb\">v. refl.</span> <i>to be angry, to quarrel</i>.");
+        assert_eq!(result[1].labels, vec![Label {
+            abbreviation: String::from("f."),
+            expansion: Some(String::from("feminine")),
+        }]);
+    }
+
+    #[test]
+    fn prefers_abbreviations_from_settings() {
+        let filename = "./src/dictionary/fixtures/labels/labels.dsl";
+        let mut settings = get_default_settings();
+        settings.abbreviations.insert(String::from("f."), String::from("female"));
+
+        let result = to_dictionary(filename, settings).unwrap();
+
+        assert_eq!(result[0].labels[0].expansion, None);
+        assert_eq!(result[1].labels[0].expansion, Some(String::from("female")));
+    }
+
+    #[test]
+    fn errors_on_include_cycle() {
+        let filename = "./src/dictionary/fixtures/includes/cycle-a.dsl";
+
+        let result = to_dictionary(filename, get_default_settings());
+
+        assert!(matches!(result, Err(HarlawError::IncludeCycle { path }) if path.ends_with("cycle-a.dsl")));
+    }
+
+    #[test]
+    fn errors_with_included_file_path() {
+        let filename = "./src/dictionary/fixtures/includes/broken-include.dsl";
+
+        let result = to_dictionary(filename, get_default_settings());
+
+        match result {
+            Err(HarlawError::Include { path, error }) => {
+                assert!(path.ends_with("missing.dsl"));
+                assert!(matches!(*error, HarlawError::Io(_)));
+            },
+            _ => panic!("Expected include error"),
+        }
+    }
+
+    #[test]
+    fn errors_on_included_file_that_is_not_dsl() {
+        let filename = "./src/dictionary/fixtures/includes/text-include.dsl";
+
+        let result = to_dictionary(filename, get_default_settings());
+
+        match result {
+            Err(HarlawError::Include { path, error }) => {
+                assert!(path.ends_with("notes.txt"));
+                assert!(matches!(*error, HarlawError::NotDsl));
+            },
+            _ => panic!("Expected include error"),
+        }
+    }
+
+    #[test]
+    fn gets_dictionary_from_str() {
+        let content = include_str!("fixtures/zoega-excerpt.dsl");
+
+        let result = from_str(content, get_default_settings()).unwrap();
+
+        assert_eq!(result[1].word, "abbadis");
+        assert_eq!(result[1].definitions[0].text, "(pl. -ar), f. <i>abbess</i>.");
+    }
+
+    #[test]
+    fn gets_dictionary_from_reader() {
+        let source = fs::File::open("./src/dictionary/fixtures/zoega-excerpt.dsl").unwrap();
+
+        let result = from_reader(source, get_default_settings()).unwrap();
+
+        assert_eq!(result[1].word, "abbadis");
+        assert_eq!(result[1].definitions[0].text, "(pl. -ar), f. <i>abbess</i>.");
+    }
+
+    #[test]
+    fn gets_dictionary_leniently_from_broken_dsl() {
+        let filename = "./src/dictionary/fixtures/broken-excerpt.dsl";
+
+        let result = to_dictionary_lenient(filename, get_default_settings()).unwrap();
+
+        assert_eq!(result.entries.len(), 3);
+        assert_eq!(result.entries[0].word, "a");
+        assert_eq!(result.entries[1].word, "abbadis");
+        assert_eq!(result.entries[2].word, "abbast");
+        assert_eq!(result.entries[2].definitions[0].text, "(að), v. refl. <i>to be angry, \u{FFFD}to quarrel</i>.");
+
+        assert_eq!(result.warnings, vec![
+            Diagnostic { line: 4, text: String::from("\t[m1]orphan definition[/m]"), category: DiagnosticCategory::OrphanDefinition },
+            Diagnostic { line: 10, text: String::from("\t[m1](að), v. refl. [i]to be angry, \u{FFFD}to quarrel[/i].[/m]"), category: DiagnosticCategory::Encoding },
+            Diagnostic { line: 11, text: String::from("abbindi"), category: DiagnosticCategory::MissingDefinition },
+            Diagnostic { line: 12, text: String::from("afbindi"), category: DiagnosticCategory::MissingDefinition },
+        ]);
+    }
+
+    #[test]
+    fn errors_on_broken_dsl_in_strict_mode() {
+        let filename = "./src/dictionary/fixtures/broken-excerpt.dsl";
+
+        let result = to_dictionary(filename, get_default_settings());
+
+        // Decoding is checked before entries.
+        assert!(matches!(result, Err(HarlawError::Encoding { line: 10 })));
+    }
+
+    #[test]
+    fn gets_dictionary_with_metadata_from_dsl() {
+        let filename = "./src/dictionary/fixtures/zoega-excerpt.dsl";
+
+        let result = to_dictionary_with_metadata(filename, get_default_settings()).unwrap();
+
+        assert_eq!(result.metadata.name, Some(String::from("A Concise Dictionary of Old Icelandic")));
+        assert_eq!(result.metadata.index_language, Some(String::from("Old Icelandic")));
+        assert_eq!(result.metadata.contents_language, Some(String::from("English")));
+        assert_eq!(result.entries[0].word, "a");
     }
 
     #[test]
@@ -65,15 +691,15 @@ mod tests {
 
         // Ensure JSON file does not already exist.
         let path_exists = Path::new(&output).exists();
-        assert_eq!(path_exists, false);
+        assert!(!path_exists);
         
         let result = to_json(input, output, settings);
         
-        assert_eq!(result, Ok(()));
+        assert!(result.is_ok());
 
         // Assert file has now been created.
         let json_exists = Path::new(&output).exists();
-        assert_eq!(json_exists, true);
+        assert!(json_exists);
 
         // Clean up created file.
         if json_exists {
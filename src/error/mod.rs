@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use crate::{Diagnostic, DiagnosticCategory};
+
+/// Error type for all harlaw operations.
+/// Line numbers refer to the original DSL file, starting from 1.
+///
+#[derive(Debug)]
+pub enum HarlawError {
+    /// Given file was not a DSL file.
+    NotDsl,
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// Line could not be decoded with the detected or given encoding.
+    Encoding { line: usize },
+    /// Given encoding label was not recognized.
+    UnsupportedEncoding(String),
+    /// Definition line without any preceding headword.
+    OrphanDefinition { line: usize },
+    /// Headword without any definition lines.
+    MissingDefinition { headword: String, line: usize },
+    /// `{{` comment without closing `}}`.
+    UnterminatedComment { line: usize },
+    /// Entries could not be serialized to JSON.
+    Json(serde_json::Error),
+    /// #INCLUDE directives form a cycle, eg. file includes itself.
+    IncludeCycle { path: String },
+    /// #INCLUDE directives are nested deeper than allowed.
+    IncludeTooDeep { path: String },
+    /// #INCLUDE directive that could not be resolved, eg. when reading from a stream.
+    UnresolvedInclude { line: usize },
+    /// Error in an included file. Line numbers of the error refer to the included file.
+    Include { path: String, error: Box<HarlawError> },
+    /// Error in the abbreviation dictionary next to DSL file.
+    Abbreviations { path: String, error: Box<HarlawError> },
+}
+
+impl fmt::Display for HarlawError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HarlawError::NotDsl => write!(f, "Given file was not a DSL file"),
+            HarlawError::Io(error) => write!(f, "Could not read or write file: {}", error),
+            HarlawError::Encoding { line } => write!(f, "Could not decode line {}", line),
+            HarlawError::UnsupportedEncoding(label) => write!(f, "Given encoding is not supported: {}", label),
+            HarlawError::OrphanDefinition { line } => write!(f, "Definition without headword on line {}", line),
+            HarlawError::MissingDefinition { headword, line } => write!(f, "Headword \"{}\" on line {} has no definition", headword, line),
+            HarlawError::UnterminatedComment { line } => write!(f, "Comment on line {} is never closed", line),
+            HarlawError::Json(error) => write!(f, "Could not stringify entries: {}", error),
+            HarlawError::IncludeCycle { path } => write!(f, "Included file {} includes itself", path),
+            HarlawError::IncludeTooDeep { path } => write!(f, "Included file {} is nested too deep", path),
+            HarlawError::UnresolvedInclude { line } => write!(f, "Included file on line {} could not be read", line),
+            HarlawError::Include { path, error } => write!(f, "In included file {}: {}", path, error),
+            HarlawError::Abbreviations { path, error } => write!(f, "In abbreviation dictionary {}: {}", path, error),
+        }
+    }
+}
+
+impl Error for HarlawError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HarlawError::Io(error) => Some(error),
+            HarlawError::Json(error) => Some(error),
+            HarlawError::Include { error, .. } | HarlawError::Abbreviations { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HarlawError {
+    fn from(error: io::Error) -> Self {
+        HarlawError::Io(error)
+    }
+}
+
+impl From<Diagnostic> for HarlawError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        let line = diagnostic.line;
+
+        match diagnostic.category {
+            DiagnosticCategory::Encoding => HarlawError::Encoding { line },
+            DiagnosticCategory::OrphanDefinition => HarlawError::OrphanDefinition { line },
+            DiagnosticCategory::MissingDefinition => HarlawError::MissingDefinition { headword: diagnostic.text, line },
+            DiagnosticCategory::UnterminatedComment => HarlawError::UnterminatedComment { line },
+            DiagnosticCategory::UnresolvedInclude => HarlawError::UnresolvedInclude { line },
+        }
+    }
+}
+
+impl From<serde_json::Error> for HarlawError {
+    fn from(error: serde_json::Error) -> Self {
+        HarlawError::Json(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays_errors_with_line_numbers() {
+        let error = HarlawError::MissingDefinition { headword: String::from("foo"), line: 12 };
+
+        assert_eq!(error.to_string(), "Headword \"foo\" on line 12 has no definition");
+        assert_eq!(HarlawError::OrphanDefinition { line: 3 }.to_string(), "Definition without headword on line 3");
+    }
+
+    #[test]
+    fn exposes_io_error_as_source() {
+        let error = HarlawError::from(io::Error::new(io::ErrorKind::NotFound, "missing"));
+
+        assert!(error.source().is_some());
+        assert!(HarlawError::NotDsl.source().is_none());
+    }
+}
@@ -1,104 +1,1579 @@
-use crate::DictionaryEntry;
-use crate::settings::{HarlawSettings, TAB, SPACE, SKIPS};
+use std::collections::VecDeque;
+use crate::{Definition, Diagnostic, DiagnosticCategory, DictionaryEntry, DictionaryMetadata, HarlawError, Label, MediaRef};
+use crate::settings::{get_default_settings, ColorMarkup, ContentReplace, LanguageMarkup, MediaMarkup, SecondaryZones, HarlawSettings, TAB, SPACE, SKIPS, INCLUDE};
+use crate::headword::{headword_display, headword_keys, headword_substitute};
+use crate::language::language_code;
+use crate::media::{media_html, media_kind};
+use crate::markup::{Attribute, Character, Markup, Node, Token, characters, close_tag, collapse_whitespace, parse_tokens, to_nodes, tokenize, markup_labels, markup_media, markup_references, markup_to_text};
 
-fn format_line(line: &str, settings: &HarlawSettings) -> String {
-    let mut formatted_line = String::from(line);
+const TILDE: char = '~';
+const CAPITALISE: char = '^';
+const SUBENTRY: char = '@';
+const COMMENT_START: &str = "{{";
+const MARGIN_START: &str = "[m";
+const MARGIN: &str = "m";
+const TAG_START: char = '[';
+const TAG_END: char = ']';
+const COLOR: &str = "c";
+const LANGUAGE: &str = "lang";
+const LABEL: &str = "p";
+const MEDIA: &str = "s";
+const SPAN_END: &str = "</span>";
+const SECONDARY: &str = "*";
+const SECONDARY_HTML: &str = "<span class=\"secondary\">";
+const DEFAULT_COLOR: &str = "green";
 
-    for remove in &settings.removes {
-       formatted_line =  formatted_line.replace(remove, "");
+// Zones extracted to their own entry fields.
+#[derive(Clone, Copy, PartialEq)]
+enum Zone {
+    Translation,
+    Example,
+    Comment,
+}
+
+const ZONES: &[(&str, Zone)] = &[
+    ("trn", Zone::Translation),
+    ("!trs", Zone::Translation),
+    ("ex", Zone::Example),
+    ("com", Zone::Comment),
+];
+
+fn capitalise(text: &str) -> String {
+    let mut characters = text.chars();
+
+    match characters.next() {
+        Some(first) => first.to_uppercase().chain(characters).collect(),
+        None => String::new(),
+    }
+}
+
+/*
+ * Unescaped ~ in definition stands for the headword of the entry,
+ * and ^~ for the headword with its first letter capitalised.
+ * Only text is substituted: escaped tildes, tags and media file names are left as they are.
+ */
+fn substitute_tilde(tokens: Vec<Token>, headword: &str) -> Vec<Token> {
+    let substitute = headword_substitute(headword);
+    let mut substituted = Vec::with_capacity(tokens.len());
+    let mut media_depth: usize = 0;
+
+    for token in tokens {
+        match &token {
+            Token::Open { name, .. } if name == MEDIA => media_depth += 1,
+            Token::Close { name, .. } if name == MEDIA => media_depth = media_depth.saturating_sub(1),
+            _ => {},
+        }
+
+        let text = match token {
+            Token::Text(text) if media_depth == 0 => text,
+            token => {
+                substituted.push(token);
+                continue;
+            },
+        };
+
+        let mut rest = text.as_str();
+
+        while let Some(position) = rest.find(TILDE) {
+            let headword = match rest[..position].strip_suffix(CAPITALISE) {
+                Some(before) => {
+                    substituted.push(Token::Text(before.to_string()));
+                    capitalise(&substitute)
+                },
+                None => {
+                    substituted.push(Token::Text(rest[..position].to_string()));
+                    substitute.clone()
+                },
+            };
+
+            substituted.extend(tokenize(&headword));
+            rest = &rest[position + TILDE.len_utf8()..];
+        }
+
+        substituted.push(Token::Text(rest.to_string()));
+    }
+
+    substituted
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Only plain color names and hex codes are accepted, anything else gets the default color.
+fn color_name(name: &str) -> &str {
+    let name = name.trim();
+
+    if name.is_empty() || !name.chars().all(|character| character.is_ascii_alphanumeric() || character == '#') {
+        return DEFAULT_COLOR;
+    }
+
+    name
+}
+
+// Removes and replaces that match a single tag, like [b] or [/m], are applied to tags, the rest to text.
+fn is_tag_pattern(pattern: &str) -> bool {
+    matches!(tokenize(pattern).as_slice(), [Token::Open { .. }] | [Token::Close { .. }])
+}
+
+// Removes and replaces of settings, split between tags and text once instead of for every node.
+struct Patterns<'a> {
+    tag_removes: Vec<&'a str>,
+    text_removes: Vec<&'a str>,
+    tag_replaces: Vec<ContentReplace<'a>>,
+    text_replaces: Vec<ContentReplace<'a>>,
+}
+
+impl<'a> Patterns<'a> {
+    fn new(settings: &HarlawSettings<'a>) -> Self {
+        let (tag_removes, text_removes) = settings.removes.iter().partition(|remove| is_tag_pattern(remove));
+        let (tag_replaces, text_replaces) = settings.replaces.iter().cloned().partition(|pattern| is_tag_pattern(pattern.search));
+
+        Patterns { tag_removes, text_removes, tag_replaces, text_replaces }
+    }
+}
+
+/*
+ * Renders parsed markup with the transforms of settings. Tags are transformed by their name,
+ * or by the removes & replaces matching them, either as written or without attributes.
+ * Other removes & replaces are applied to text, so escaped characters never match them.
+ */
+struct Renderer<'s, 'a> {
+    settings: &'s HarlawSettings<'a>,
+    patterns: &'s Patterns<'a>,
+    // Text is escaped for HTML, and tags not transformed to HTML are left out.
+    html: bool,
+}
+
+impl<'s, 'a> Renderer<'s, 'a> {
+    fn render(&self, markup: &[Markup]) -> String {
+        markup.iter().map(|node| self.render_node(node)).collect()
+    }
+
+    fn render_node(&self, node: &Markup) -> String {
+        match node {
+            Markup::Text(text) => self.render_text(text),
+            Markup::Escaped(character) if self.html => escape_html(&character.to_string()),
+            Markup::Escaped(character) => character.to_string(),
+            Markup::Close { name, raw } => self.render_close(name, raw),
+            Markup::Tag { name, attrs, raw, children } => self.render_tag(name, attrs, raw, children),
+        }
+    }
+
+    fn render_text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+
+        for remove in &self.patterns.text_removes {
+            text = text.replace(remove, "");
+        }
+
+        for pattern in &self.patterns.text_replaces {
+            text = text.replace(pattern.search, pattern.replace);
+        }
+
+        if self.html {
+            return escape_html(&text);
+        }
+
+        text
+    }
+
+    fn render_tag(&self, name: &str, attrs: &[Attribute], raw: &str, children: &[Markup]) -> String {
+        let settings = self.settings;
+
+        match name {
+            SECONDARY => match settings.secondary {
+                SecondaryZones::Keep => self.render_generic(name, raw, children),
+                SecondaryZones::Drop => String::new(),
+                SecondaryZones::Inline => self.render(children),
+                SecondaryZones::Wrap => self.render_span(SECONDARY_HTML, children),
+            },
+            // Media tags are transformed along with their content, which is the name of media file.
+            MEDIA => match settings.media {
+                MediaMarkup::Keep => self.render_generic(name, raw, children),
+                MediaMarkup::Remove => String::new(),
+                MediaMarkup::Html(urls) => media_html(markup_to_text(&to_nodes(children)).trim(), &urls),
+            },
+            COLOR => {
+                let content = raw.trim_start_matches(TAG_START).trim_end_matches(TAG_END).trim();
+                let color = content.strip_prefix(name).unwrap_or_default();
+
+                match settings.colors {
+                    ColorMarkup::Keep => self.render_generic(name, raw, children),
+                    ColorMarkup::Remove => self.render(children),
+                    ColorMarkup::Style => self.render_span(&format!("<span style=\"color:{}\">", color_name(color)), children),
+                    ColorMarkup::Class(prefix) => self.render_span(&format!("<span class=\"{}{}\">", prefix, color_name(color)), children),
+                }
+            },
+            // Language tag without known language is removed, as there is nothing to tell.
+            LANGUAGE => match (settings.languages, language_code(attrs)) {
+                (LanguageMarkup::Keep, _) => self.render_generic(name, raw, children),
+                (LanguageMarkup::Span, Some(code)) => self.render_span(&format!("<span lang=\"{}\">", code), children),
+                _ => self.render(children),
+            },
+            _ => self.render_generic(name, raw, children),
+        }
+    }
+
+    fn render_span(&self, open_html: &str, children: &[Markup]) -> String {
+        format!("{}{}{}", open_html, self.render(children), SPAN_END)
+    }
+
+    // Removes win over replaces, like they are applied first.
+    fn transform_tag(&self, tag: &str, bare_tag: &str) -> Option<&'a str> {
+        let matches = |pattern: &str| pattern == tag || pattern == bare_tag;
+
+        if self.patterns.tag_removes.iter().any(|remove| matches(remove)) {
+            return Some("");
+        }
+
+        self.patterns.tag_replaces.iter().find(|pattern| matches(pattern.search)).map(|pattern| pattern.replace)
+    }
+
+    fn untransformed(&self, tag: &str) -> String {
+        if self.html {
+            return String::new();
+        }
+
+        tag.to_string()
+    }
+
+    fn render_generic(&self, name: &str, raw: &str, children: &[Markup]) -> String {
+        let open_html = match self.transform_tag(raw, &format!("{}{}{}", TAG_START, name, TAG_END)) {
+            Some(html) if name == LABEL => self.add_label_title(html, children),
+            Some(html) => html.to_string(),
+            None => self.untransformed(raw),
+        };
+
+        format!("{}{}{}", open_html, self.render(children), self.render_close(name, &close_tag(name)))
+    }
+
+    fn render_close(&self, name: &str, raw: &str) -> String {
+        match self.transform_tag(raw, &close_tag(name)) {
+            Some(html) => html.to_string(),
+            None => self.untransformed(raw),
+        }
+    }
+
+    // Labels with known expansion get it as title, when settings transform [p] to HTML element: <span title="feminine">
+    fn add_label_title(&self, open_html: &str, children: &[Markup]) -> String {
+        let label = collapse_whitespace(&markup_to_text(&to_nodes(children)));
+
+        match self.settings.abbreviations.get(&label) {
+            Some(expansion) if open_html.starts_with('<') && open_html.ends_with('>') => {
+                format!("{} title=\"{}\">", &open_html[..open_html.len() - 1], escape_html(expansion))
+            },
+            _ => open_html.to_string(),
+        }
+    }
+}
+
+/// Markup rendered as HTML, with the transforms of default settings.
+pub fn markup_to_html(markup: &[Markup]) -> String {
+    let settings = get_default_settings();
+    let patterns = Patterns::new(&settings);
+
+    Renderer { settings: &settings, patterns: &patterns, html: true }.render(markup)
+}
+
+fn format_markup(markup: &[Markup], settings: &HarlawSettings, patterns: &Patterns) -> String {
+    Renderer { settings, patterns, html: false }.render(markup).trim().to_string()
+}
+
+// Margin tags like [m2] and [m] are only layout, they come before the content of line.
+fn is_margin_tag(name: &str) -> bool {
+    name.strip_prefix(MARGIN).is_some_and(|level| level.chars().all(|character| character.is_ascii_digit()))
+}
+
+// Position of the content of definition line, after leading whitespace and margin tag.
+fn content_start(tokens: &[Token]) -> usize {
+    tokens
+        .iter()
+        .position(|token| match token {
+            Token::Text(text) => !text.trim().is_empty(),
+            Token::Open { name, .. } => !is_margin_tag(name),
+            _ => true,
+        })
+        .unwrap_or(tokens.len())
+}
+
+/*
+ * Parses definition lines of card. Secondary zone left open at the end of line
+ * continues on the next line, so it is opened again at the start of its content.
+ * With Keep policy, tags are left as they are written.
+ */
+fn parse_definitions(lines: &[String], settings: &HarlawSettings, headword: &str) -> Vec<Vec<Markup>> {
+    let mut in_secondary = false;
+
+    lines
+        .iter()
+        .map(|line| {
+            let mut tokens = tokenize(line);
+
+            if settings.substitute_tilde {
+                tokens = substitute_tilde(tokens, headword);
+            }
+
+            if in_secondary && settings.secondary != SecondaryZones::Keep {
+                let raw = format!("{}{}{}", TAG_START, SECONDARY, TAG_END);
+                tokens.insert(content_start(&tokens), Token::Open { name: String::from(SECONDARY), attrs: vec![], raw });
+            }
+
+            for token in &tokens {
+                match token {
+                    Token::Open { name, .. } if name == SECONDARY => in_secondary = true,
+                    Token::Close { name, .. } if name == SECONDARY => in_secondary = false,
+                    _ => {},
+                }
+            }
+
+            let markup = parse_tokens(tokens);
+
+            // Dropped zones are left out before anything is collected from the definition.
+            match settings.secondary {
+                SecondaryZones::Drop => drop_secondary(markup),
+                _ => markup,
+            }
+        })
+        .collect()
+}
+
+fn drop_secondary(markup: Vec<Markup>) -> Vec<Markup> {
+    markup
+        .into_iter()
+        .filter_map(|node| match node {
+            Markup::Tag { name, .. } if name == SECONDARY => None,
+            Markup::Tag { name, attrs, raw, children } => Some(Markup::Tag { name, attrs, raw, children: drop_secondary(children) }),
+            node => Some(node),
+        })
+        .collect()
+}
+
+/*
+ * Margin level of definition line, from its leading [mN] tag.
+ * Lines without explicit level are on level 0.
+ */
+fn margin_level(line: &str) -> u8 {
+    line.trim_start()
+        .strip_prefix(MARGIN_START)
+        .and_then(|rest| rest.split_once(']'))
+        .and_then(|(level, _)| level.parse().ok())
+        .unwrap_or(0)
+}
+
+// Headwords referenced from definitions, each only once.
+fn definition_references(definitions: &[Vec<Node>]) -> Vec<String> {
+    let mut references: Vec<String> = vec![];
+
+    for definition in definitions {
+        for reference in markup_references(definition) {
+            if !references.contains(&reference) {
+                references.push(reference);
+            }
+        }
+    }
+
+    references
+}
+
+// Zones of the definition, outermost zone wins when zones are nested.
+fn find_zones<'m>(markup: &'m [Markup], zones: &mut Vec<(Zone, &'m [Markup])>) {
+    for node in markup {
+        if let Markup::Tag { name, children, .. } = node {
+            match ZONES.iter().find(|(zone_name, _)| zone_name == name) {
+                Some((_, zone)) => zones.push((*zone, children)),
+                None => find_zones(children, zones),
+            }
+        }
+    }
+}
+
+// Labels of the card, each only once, with their expansions.
+fn definition_labels(definitions: &[Vec<Node>], settings: &HarlawSettings) -> Vec<Label> {
+    let mut labels: Vec<Label> = vec![];
+
+    for definition in definitions {
+        for abbreviation in markup_labels(definition) {
+            if labels.iter().any(|label| label.abbreviation == abbreviation) {
+                continue;
+            }
+
+            labels.push(Label {
+                expansion: settings.abbreviations.get(&abbreviation).cloned(),
+                abbreviation,
+            });
+        }
+    }
+
+    labels
+}
+
+// Media files of the card, each only once.
+fn definition_media(definitions: &[Vec<Node>]) -> Vec<MediaRef> {
+    let mut media: Vec<MediaRef> = vec![];
+
+    for definition in definitions {
+        for file in markup_media(definition) {
+            if media.iter().any(|existing| existing.file == file) {
+                continue;
+            }
+
+            media.push(MediaRef {
+                kind: media_kind(&file),
+                file,
+                location: None,
+            });
+        }
+    }
+
+    media
+}
+
+fn is_empty_line(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+// Metadata lines should not be transformed.
+fn is_metadata_line(first_character: &str) -> bool {
+    SKIPS.contains(&first_character)
+}
+
+// Line is definition line if first char is tab or space.
+fn is_definition_line(first_character: &str) -> bool {
+    first_character.eq(TAB) || first_character.eq(&SPACE.to_string())
+}
+
+// Header line like #NAME	"Dictionary name" -> ("NAME", "Dictionary name")
+fn parse_metadata_line(line: &str) -> Option<(String, String)> {
+    let header = line.trim().strip_prefix('#')?;
+    let (key, value) = match header.find(char::is_whitespace) {
+        Some(position) => header.split_at(position),
+        None => (header, ""),
+    };
+    let value = value.trim().trim_matches('"').trim();
+
+    Some((key.to_uppercase(), value.to_string()))
+}
+
+pub fn format_metadata(lines: &[String]) -> DictionaryMetadata {
+    let mut metadata = DictionaryMetadata::default();
+
+    for line in lines {
+        if is_empty_line(line) {
+            continue;
+        }
+
+        // Headers are only at the beginning of the file.
+        let (key, value) = match parse_metadata_line(line) {
+            Some(header) => header,
+            None => break,
+        };
+
+        match key.as_str() {
+            "NAME" => metadata.name = Some(value),
+            "INDEX_LANGUAGE" => metadata.index_language = Some(value),
+            "CONTENTS_LANGUAGE" => metadata.contents_language = Some(value),
+            "SOURCE_CODE_PAGE" => metadata.source_code_page = Some(value),
+            // Include is a directive for entries, not metadata.
+            "INCLUDE" => {},
+            _ => {
+                metadata.extra.insert(key, value);
+            },
+        }
+    }
+
+    metadata
+}
+
+/*
+ * DSL files may contain many headwords in a row.
+ * This means the headwords use identical definitions, which come after them.
+ * Parser collects headwords of the card until its definitions are done,
+ * and problems are returned as diagnostics, leaving the parser ready for next line.
+ */
+pub struct CardParser<'a> {
+    settings: HarlawSettings<'a>,
+    patterns: Patterns<'a>,
+    headwords: Vec<(usize, String)>,
+    definitions: Vec<String>,
+    // Subentries of the card, with their headwords and definitions.
+    subentries: Vec<(String, Vec<String>)>,
+    in_subentry: bool,
+    notes: Vec<String>,
+    // Comment that is still open at the end of previous line.
+    open_comment: Option<String>,
+    // Line where the open comment started, with its content and notes so far.
+    // The rest of the line after the comment continues it.
+    commented_line: Option<(usize, String, Vec<String>)>,
+    entries: VecDeque<DictionaryEntry>,
+}
+
+impl<'a> CardParser<'a> {
+    pub fn new(settings: HarlawSettings<'a>) -> Self {
+        CardParser {
+            patterns: Patterns::new(&settings),
+            settings,
+            headwords: vec![],
+            definitions: vec![],
+            subentries: vec![],
+            in_subentry: false,
+            notes: vec![],
+            open_comment: None,
+            commented_line: None,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /*
+     * Removes {{comments}} from line, collecting their text. Comments may span multiple lines,
+     * so comment left open continues on the next line. Escaped braces do not start or end comments.
+     */
+    fn strip_comments(&mut self, line: &str) -> (String, Vec<String>) {
+        let mut content = String::with_capacity(line.len());
+        // Content between the comments closing on this line.
+        let mut pieces = vec![];
+        let mut notes = vec![];
+        let mut characters = characters(line).peekable();
+
+        while let Some(character) = characters.next() {
+            let next = characters.peek().copied();
+
+            match (&mut self.open_comment, character, next) {
+                (Some(comment), Character::Plain('}'), Some(Character::Plain('}'))) => {
+                    characters.next();
+                    notes.push(comment.trim().to_string());
+                    self.open_comment = None;
+                    pieces.push(std::mem::take(&mut content));
+                },
+                (None, Character::Plain('{'), Some(Character::Plain('{'))) => {
+                    characters.next();
+                    self.open_comment = Some(String::new());
+                },
+                (Some(comment), character, _) => character.push_dsl(comment),
+                (None, character, _) => character.push_dsl(&mut content),
+            }
+        }
+
+        if let Some(comment) = &mut self.open_comment {
+            comment.push('\n');
+        }
+
+        let content = pieces
+            .into_iter()
+            .chain(std::iter::once(content))
+            .reduce(|before, after| join_around_comment(&before, &after))
+            .unwrap_or_default();
+
+        (content, notes.into_iter().filter(|note| !note.is_empty()).collect())
+    }
+
+    pub fn push_line(&mut self, line_number: usize, line: &str) -> Result<(), Diagnostic> {
+        let (content, mut notes) = self.strip_comments(line);
+
+        // Line with multi-line comment goes on after the comment, as if the comment was on one line.
+        let (line_number, content) = match self.commented_line.take() {
+            Some((start_line, before, mut before_notes)) => {
+                before_notes.append(&mut notes);
+                notes = before_notes;
+                (start_line, join_around_comment(&before, &content))
+            },
+            None => (line_number, content),
+        };
+
+        if self.open_comment.is_some() {
+            self.commented_line = Some((line_number, content, notes));
+            return Ok(());
+        }
+
+        let result = self.push_content(line_number, &content);
+
+        // Comments belong to the card of the line, which may have been started by this very line.
+        self.notes.extend(notes);
+
+        result
+    }
+
+    // Include directives inside comments are commented out as well.
+    pub fn in_comment(&self) -> bool {
+        self.open_comment.is_some()
+    }
+
+    fn push_content(&mut self, line_number: usize, line: &str) -> Result<(), Diagnostic> {
+        let first_character = match line.chars().next() {
+            Some(character) if !is_empty_line(line) => character.to_string(),
+            _ => return Ok(()),
+        };
+
+        // Skip metadata lines.
+        if is_metadata_line(&first_character) {
+            return Ok(());
+        }
+
+        // If line startes with starting character, it is definition of current headwords.
+        if is_definition_line(&first_character) {
+            if self.headwords.is_empty() {
+                return Err(Diagnostic {
+                    line: line_number,
+                    text: line.to_string(),
+                    category: DiagnosticCategory::OrphanDefinition,
+                });
+            }
+
+            self.push_definition(line);
+            return Ok(());
+        }
+
+        // The line is a headword. If previous card has definitions, it is complete.
+        if self.has_definitions() {
+            self.flush_card();
+        }
+
+        self.headwords.push((line_number, line.to_string()));
+        Ok(())
+    }
+
+    /*
+     * Definition line starting with @ starts a subentry, with the rest of the line as its headword.
+     * Following definitions belong to the subentry, until a line with bare @ returns to the main entry.
+     */
+    fn push_definition(&mut self, line: &str) {
+        match line.trim().strip_prefix(SUBENTRY) {
+            Some(headword) if headword.trim().is_empty() => self.in_subentry = false,
+            Some(headword) => {
+                self.subentries.push((headword.trim().to_string(), vec![]));
+                self.in_subentry = true;
+            },
+            None => match self.subentries.last_mut() {
+                Some((_, definitions)) if self.in_subentry => definitions.push(line.to_string()),
+                _ => self.definitions.push(line.to_string()),
+            },
+        }
+    }
+
+    fn has_definitions(&self) -> bool {
+        !self.definitions.is_empty() || !self.subentries.is_empty()
+    }
+
+    // Number of headwords still waiting for their definitions.
+    pub fn pending_headwords(&self) -> usize {
+        if self.has_definitions() {
+            return 0;
+        }
+
+        self.headwords.len()
+    }
+
+    /*
+     * Drops headwords waiting for definitions, except the last one, which may still get them.
+     * Each dropped headword is reported as missing its definition.
+     */
+    pub fn drop_pending_headwords(&mut self) -> Vec<Diagnostic> {
+        let last = self.headwords.pop();
+        let dropped = self.missing_definitions();
+
+        self.headwords.extend(last);
+        dropped
+    }
+
+    fn missing_definitions(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.headwords)
+            .into_iter()
+            .map(|(line, headword)| Diagnostic {
+                line,
+                text: self.format_headword(&headword),
+                category: DiagnosticCategory::MissingDefinition,
+            })
+            .collect()
+    }
+
+    pub fn next_entry(&mut self) -> Option<DictionaryEntry> {
+        self.entries.pop_front()
+    }
+
+    // Headword is displayed without braces of its unsorted parts.
+    fn format_headword(&self, headword: &str) -> String {
+        format_markup(&parse_tokens(tokenize(&headword_display(headword))), &self.settings, &self.patterns)
+    }
+
+    // Zones are formatted like definitions, and sorted to entry fields by their kind.
+    fn extract_zones(&self, entry: &mut DictionaryEntry, definitions: &[Vec<Markup>]) {
+        let mut zones = vec![];
+
+        for definition in definitions {
+            find_zones(definition, &mut zones);
+        }
+
+        for (zone, content) in zones {
+            let formatted = format_markup(content, &self.settings, &self.patterns);
+
+            if formatted.is_empty() {
+                continue;
+            }
+
+            match zone {
+                Zone::Translation => entry.translations.push(formatted),
+                Zone::Example => entry.examples.push(formatted),
+                Zone::Comment => entry.comments.push(formatted),
+            }
+        }
+    }
+
+    /*
+     * Entry of headword with its definitions. Tildes in definitions stand for
+     * the given tilde headword, which is the main headword also in subentries.
+     */
+    fn build_entry(&self, headword: &str, tilde_headword: &str, raw_definitions: &[String]) -> DictionaryEntry {
+        let markup = parse_definitions(raw_definitions, &self.settings, tilde_headword);
+        let nodes: Vec<Vec<Node>> = markup.iter().map(|definition| to_nodes(definition)).collect();
+        let mut definitions: Vec<Definition> = raw_definitions
+            .iter()
+            .zip(&markup)
+            .map(|(raw_definition, definition)| Definition {
+                level: margin_level(raw_definition),
+                text: format_markup(definition, &self.settings, &self.patterns),
+            })
+            .collect();
+
+        // Definitions that only had secondary content are left out of compact entries.
+        if self.settings.secondary == SecondaryZones::Drop {
+            definitions.retain(|definition| !definition.text.is_empty());
+        }
+
+        let mut entry = DictionaryEntry {
+            word: self.format_headword(headword),
+            keys: headword_keys(headword),
+            definitions,
+            references: definition_references(&nodes),
+            labels: definition_labels(&nodes, &self.settings),
+            media: definition_media(&nodes),
+            ..Default::default()
+        };
+
+        if self.settings.extract_zones {
+            self.extract_zones(&mut entry, &markup);
+        }
+
+        entry
+    }
+
+    // Subentry headwords may also use tilde for the main headword.
+    fn subentry_headword(&self, subentry: &str, headword: &str) -> String {
+        if !self.settings.substitute_tilde {
+            return subentry.to_string();
+        }
+
+        substitute_tilde(tokenize(subentry), headword).iter().map(Token::to_dsl).collect()
+    }
+
+    // Every headword of the card shares the same definitions and subentries.
+    fn flush_card(&mut self) {
+        let mut flattened: Vec<String> = vec![];
+
+        for (_line, headword) in std::mem::take(&mut self.headwords) {
+            let mut entry = self.build_entry(&headword, &headword, &self.definitions);
+
+            if self.settings.keep_notes {
+                entry.notes = self.notes.clone();
+            }
+
+            let subentries: Vec<DictionaryEntry> = self.subentries
+                .iter()
+                .map(|(subentry, definitions)| {
+                    self.build_entry(&self.subentry_headword(subentry, &headword), &headword, definitions)
+                })
+                .collect();
+
+            if !self.settings.flatten_subentries {
+                entry.subentries = subentries;
+                self.entries.push_back(entry);
+                continue;
+            }
+
+            self.entries.push_back(entry);
+
+            // Headwords of a group share subentries, so flattened subentry is added only once.
+            for subentry in subentries {
+                if !flattened.contains(&subentry.word) {
+                    flattened.push(subentry.word.clone());
+                    self.entries.push_back(subentry);
+                }
+            }
+        }
+
+        self.definitions.clear();
+        self.subentries.clear();
+        self.in_subentry = false;
+        self.notes.clear();
+    }
+
+    /*
+     * Comment left open takes the rest of the source, which is reported.
+     * Content of its line before the comment is still used.
+     */
+    fn close_comment(&mut self) -> Vec<Diagnostic> {
+        let comment = match self.open_comment.take() {
+            Some(comment) => comment,
+            None => return vec![],
+        };
+        let mut diagnostics = vec![];
+
+        if let Some((line, content, notes)) = self.commented_line.take() {
+            diagnostics.push(Diagnostic {
+                line,
+                text: format!("{}{}", COMMENT_START, comment.lines().next().unwrap_or_default()),
+                category: DiagnosticCategory::UnterminatedComment,
+            });
+            diagnostics.extend(self.push_content(line, &content).err());
+            self.notes.extend(notes);
+        }
+
+        diagnostics
+    }
+
+    // Completes the last card. Headwords without definitions are dropped and reported.
+    pub fn finish(&mut self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.close_comment();
+
+        if self.has_definitions() {
+            self.flush_card();
+        } else {
+            self.notes.clear();
+            diagnostics.extend(self.missing_definitions());
+        }
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+        diagnostics
+    }
+}
+
+/*
+ * Joins the content before and after comment, which may span multiple lines.
+ * Whitespace around the comment is collapsed to one space: "Dolor {{...}} sit" -> "Dolor sit"
+ * Indentation before the comment is kept, so that the line stays as it is.
+ */
+fn join_around_comment(before: &str, after: &str) -> String {
+    if is_empty_line(before) {
+        return format!("{}{}", before, after.trim_start());
+    }
+
+    let (before_trimmed, after_trimmed) = (before.trim_end(), after.trim_start());
+
+    if after_trimmed.is_empty() {
+        return before_trimmed.to_string();
+    }
+
+    let separator = if before_trimmed.len() < before.len() || after_trimmed.len() < after.len() { " " } else { "" };
+
+    format!("{}{}{}", before_trimmed, separator, after_trimmed)
+}
+
+// #INCLUDE "part2.dsl" -> "part2.dsl", only when unindented like other directives.
+pub(crate) fn parse_include_line(line: &str) -> Option<&str> {
+    let target = line.strip_prefix(INCLUDE)?;
+
+    Some(target.trim().trim_matches('"').trim())
+}
+
+pub fn format_entries(lines: Vec<String>, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    format_entries_with_includes(lines, settings, |_include| Ok(vec![]))
+}
+
+/*
+ * Entries of #INCLUDE files are spliced in place of the directive.
+ * Reading the included file is up to the caller, formatter only
+ * tells where it goes.
+ */
+pub fn format_entries_with_includes<F>(lines: Vec<String>, settings: HarlawSettings, mut resolve_include: F) -> Result<Vec<DictionaryEntry>, HarlawError>
+where F: FnMut(&str) -> Result<Vec<DictionaryEntry>, HarlawError>, {
+    let mut parser = CardParser::new(settings);
+    let mut dictionary_entries: Vec<DictionaryEntry> = vec![];
+
+    for (line_index, line) in lines.iter().enumerate() {
+        if let Some(include) = parse_include_line(line).filter(|_| !parser.in_comment()) {
+            // Card before the directive is complete, so its headwords must have definitions by now.
+            if let Some(diagnostic) = parser.finish().into_iter().next() {
+                return Err(diagnostic.into());
+            }
+
+            dictionary_entries.extend(parser.entries.drain(..));
+            dictionary_entries.extend(resolve_include(include)?);
+            continue;
+        }
+
+        parser.push_line(line_index + 1, line)?;
+    }
+
+    if let Some(diagnostic) = parser.finish().into_iter().next() {
+        return Err(diagnostic.into());
+    }
+
+    dictionary_entries.extend(parser.entries);
+
+    Ok(dictionary_entries)
+}
+
+/*
+ * Lenient version of format_entries_with_includes: problems are collected
+ * as diagnostics, and parsing continues. An include that cannot be resolved
+ * is skipped and reported as well.
+ */
+pub fn format_entries_leniently<F>(lines: Vec<String>, settings: HarlawSettings, mut resolve_include: F) -> (Vec<DictionaryEntry>, Vec<Diagnostic>)
+where F: FnMut(&str) -> Option<Vec<DictionaryEntry>>, {
+    let mut parser = CardParser::new(settings);
+    let mut dictionary_entries: Vec<DictionaryEntry> = vec![];
+    let mut diagnostics = vec![];
+
+    for (line_index, line) in lines.iter().enumerate() {
+        if let Some(include) = parse_include_line(line).filter(|_| !parser.in_comment()) {
+            diagnostics.extend(parser.finish());
+            dictionary_entries.extend(parser.entries.drain(..));
+
+            match resolve_include(include) {
+                Some(entries) => dictionary_entries.extend(entries),
+                None => diagnostics.push(Diagnostic {
+                    line: line_index + 1,
+                    text: line.clone(),
+                    category: DiagnosticCategory::UnresolvedInclude,
+                }),
+            }
+            continue;
+        }
+
+        if let Err(diagnostic) = parser.push_line(line_index + 1, line) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    diagnostics.extend(parser.finish());
+    dictionary_entries.extend(parser.entries);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+    (dictionary_entries, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{get_no_markup_settings, ContentReplace, MediaUrls};
+    use crate::MediaKind;
+
+    fn texts(definitions: &[Definition]) -> Vec<&str> {
+        definitions.iter().map(|definition| definition.text.as_str()).collect()
+    }
+
+    fn format_line(line: &str, settings: &HarlawSettings) -> String {
+        format_markup(&parse_tokens(tokenize(line)), settings, &Patterns::new(settings))
+    }
+
+    #[test]
+    fn formats_line() {
+        let line = "	[m1]Lorem ipsum [b]dolor[/b] sit amet, dolor sit igitur[/m]";        
+        
+        let result = format_line(line, &get_default_settings());
+
+        assert_eq!(result, String::from("Lorem ipsum <strong>dolor</strong> sit amet, dolor sit igitur"));
+    }
+
+    #[test]
+    fn formats_metadata() {
+        let lines = vec![
+            String::from("#NAME	\"Test Dictionary fixture\""),
+            String::from("#INDEX_LANGUAGE	\"English\""),
+            String::from("#CONTENTS_LANGUAGE \"Latin\""),
+            String::from("#SOURCE_CODE_PAGE	Latin"),
+            String::from(""),
+            String::from("#ICON_FILE	\"test.bmp\""),
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+            String::from("#NOT_A_HEADER	\"Ignored\""),
+        ];
+
+        let result = format_metadata(&lines);
+
+        assert_eq!(result.name, Some(String::from("Test Dictionary fixture")));
+        assert_eq!(result.index_language, Some(String::from("English")));
+        assert_eq!(result.contents_language, Some(String::from("Latin")));
+        assert_eq!(result.source_code_page, Some(String::from("Latin")));
+        assert_eq!(result.extra.len(), 1);
+        assert_eq!(result.extra["ICON_FILE"], "test.bmp");
+    }
+
+    #[test]
+    fn unescapes_literal_characters() {
+        let line = "	[m1]\\[b\\]not bold\\[/b\\] \\{-\\} \\~ \\@ \\# C:\\\\ [b]bold[/b][/m]";
+
+        let result = format_line(line, &get_default_settings());
+
+        assert_eq!(result, String::from("[b]not bold[/b] {-} ~ @ # C:\\ <strong>bold</strong>"));
+    }
+
+    #[test]
+    fn keeps_private_use_characters() {
+        let line = "	[m1]\u{E000}foo\u{E001} \u{E004}[/m]";
+
+        let result = format_line(line, &get_default_settings());
+
+        assert_eq!(result, String::from("\u{E000}foo\u{E001} \u{E004}"));
+    }
+
+    #[test]
+    fn never_matches_escaped_brackets_in_removes() {
+        let line = "\\[m1\\]foo\\[/m\\]";
+
+        let result = format_line(line, &get_no_markup_settings());
+
+        assert_eq!(result, String::from("[m1]foo[/m]"));
+    }
+
+    #[test]
+    fn applies_tag_settings_to_tags_only() {
+        let settings = HarlawSettings {
+            replaces: vec![ContentReplace { search: "[ref]", replace: "<a>" }, ContentReplace { search: "[/ref]", replace: "</a>" }],
+            removes: vec!["[/m]", "ref"],
+            ..Default::default()
+        };
+
+        let result = format_line("[ref dict=\"x\"]ref[/ref] [b]bar[/b][/m]", &settings);
+
+        assert_eq!(result, String::from("<a></a> [b]bar[/b]"));
+    }
+
+    #[test]
+    fn unescapes_headwords() {
+        let lines = vec![
+            String::from("\\#1 \\[sic\\]"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result[0].word, "#1 [sic]");
+    }
+
+    #[test]
+    fn substitutes_tilde_with_headword() {
+        let lines = vec![
+            String::from("{to }go"),
+            String::from("abbindi(s)"),
+            String::from("	[m1]~ away. ^~ \\~ here.[/m]"),
+        ];
+        let settings = HarlawSettings {
+            substitute_tilde: true,
+            ..get_default_settings()
+        };
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result[0].definitions[0].text, "go away. Go ~ here.");
+        assert_eq!(result[1].definitions[0].text, "abbindi(s) away. Abbindi(s) ~ here.");
+    }
+
+    #[test]
+    fn substitutes_tilde_only_in_text() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1][lang name=\"~\"]~[/lang] [s]~x.wav[/s][/m]"),
+        ];
+        let settings = HarlawSettings {
+            substitute_tilde: true,
+            ..get_no_markup_settings()
+        };
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result[0].definitions[0].text, "hús");
+        assert_eq!(result[0].media[0].file, "~x.wav");
+    }
+
+    #[test]
+    fn keeps_tilde_by_default() {
+        let lines = vec![
+            String::from("go"),
+            String::from("	[m1]~ away.[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result[0].definitions[0].text, "~ away.");
+    }
+
+    #[test]
+    fn keeps_margin_levels_of_definitions() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+            String::from("	[m2][ex]Dolor sit amet[/ex][/m]"),
+            String::from("	Consectetur"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result[0].definitions, vec![
+            Definition { level: 1, text: String::from("Lorem ipsum") },
+            Definition { level: 2, text: String::from("Dolor sit amet") },
+            Definition { level: 0, text: String::from("Consectetur") },
+        ]);
+    }
+
+    #[test]
+    fn collects_references_of_card() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	[m1]See [ref]bar[/ref].[/m]"),
+            String::from("	[m2]Also <<baz>> and [ref]bar[/ref].[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result[0].references, vec!["bar", "baz"]);
+        assert_eq!(result[0].definitions[0].text, "See <span class=\"reference\">bar</span>.");
+    }
+
+    #[test]
+    fn extracts_zones_to_entry_fields() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1][trn]house, [i]home[/i][/trn] [com](old)[/com][/m]"),
+            String::from("	[m2][ex]~ið er stórt[/ex] [!trs]building[/!trs][/m]"),
+        ];
+        let settings = HarlawSettings {
+            extract_zones: true,
+            substitute_tilde: true,
+            ..get_default_settings()
+        };
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result[0].translations, vec!["house, <i>home</i>", "building"]);
+        assert_eq!(result[0].examples, vec!["húsið er stórt"]);
+        assert_eq!(result[0].comments, vec!["(old)"]);
+        assert_eq!(result[0].definitions[0].text, "house, <i>home</i> (old)");
+    }
+
+    #[test]
+    fn leaves_zones_in_definitions_by_default() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1][trn]house[/trn][/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert!(result[0].translations.is_empty());
+        assert_eq!(result[0].definitions[0].text, "house");
+    }
+
+    #[test]
+    fn expands_labels_with_abbreviations() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1][p]n.[/p] [p]pl.[/p] house[/m]"),
+        ];
+        let mut settings = get_default_settings();
+        settings.abbreviations.insert(String::from("n."), String::from("neuter \"noun\""));
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result[0].definitions[0].text, "<span title=\"neuter &quot;noun&quot;\">n.</span> <span>pl.</span> house");
+        assert_eq!(result[0].labels, vec![
+            Label { abbreviation: String::from("n."), expansion: Some(String::from("neuter \"noun\"")) },
+            Label { abbreviation: String::from("pl."), expansion: None },
+        ]);
+    }
+
+    #[test]
+    fn expands_labels_with_extra_whitespace() {
+        let lines = vec![
+            String::from("ábúð"),
+            String::from("	[m1][p]f. [/p] [p]v.  refl.[/p] dwelling[/m]"),
+        ];
+        let mut settings = get_default_settings();
+        settings.abbreviations.insert(String::from("f."), String::from("feminine"));
+        settings.abbreviations.insert(String::from("v. refl."), String::from("reflexive verb"));
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result[0].definitions[0].text, "<span title=\"feminine\">f. </span> <span title=\"reflexive verb\">v.  refl.</span> dwelling");
+        assert_eq!(result[0].labels[0].expansion, Some(String::from("feminine")));
+    }
+
+    #[test]
+    fn removes_labels_without_markup() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1][p]n.[/p] house[/m]"),
+        ];
+        let mut settings = get_no_markup_settings();
+        settings.abbreviations.insert(String::from("n."), String::from("neuter"));
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result[0].definitions[0].text, "n. house");
+        assert_eq!(result[0].labels[0].expansion, Some(String::from("neuter")));
+    }
+
+    #[test]
+    fn formats_any_color_as_style() {
+        let line = "[c red]foo[/c] [c]bar[/c] [c mediumslateblue][com]baz[/com][/c] [c x\"y]qux[/c]";
+
+        let result = format_line(line, &get_default_settings());
+
+        assert_eq!(result, "<span style=\"color:red\">foo</span> <span style=\"color:green\">bar</span> <span style=\"color:mediumslateblue\">baz</span> <span style=\"color:green\">qux</span>");
+    }
+
+    #[test]
+    fn formats_colors_as_class() {
+        let settings = HarlawSettings {
+            colors: ColorMarkup::Class("color-"),
+            ..get_default_settings()
+        };
+
+        let result = format_line("[c red]foo[/c] \\[c\\]", &settings);
+
+        assert_eq!(result, "<span class=\"color-red\">foo</span> [c]");
     }
 
-    for pattern in &settings.replaces {
-        formatted_line =  formatted_line.replace(pattern.search, pattern.replace);
-     }
+    #[test]
+    fn removes_any_color_without_markup() {
+        let result = format_line("[c unknowncolor]foo[/c] [c]bar[/c]", &get_no_markup_settings());
 
-     formatted_line = formatted_line.trim().to_string();
+        assert_eq!(result, "foo bar");
+    }
 
-    formatted_line  
-}
+    #[test]
+    fn formats_language_tags_as_spans() {
+        let line = "[lang id=1031]Haus[/lang] [lang name=\"Icelandic\"]hús[/lang] [lang id=1]x[/lang] [lang]y[/lang]";
 
-fn is_empty_line(line: &str) -> bool {
-    line.trim().is_empty()
-}
+        let result = format_line(line, &get_default_settings());
 
-// Metadata lines should not be transformed.
-fn is_metadata_line(first_character: &str) -> bool {
-    SKIPS.contains(&first_character)
-}
+        assert_eq!(result, "<span lang=\"de\">Haus</span> <span lang=\"is\">hús</span> x y");
+    }
 
-// Line is definition line if first char is tab or space.
-fn is_definition_line(first_character: &str) -> bool {
-    first_character.eq(TAB) || first_character.eq(&SPACE.to_string())
-}
+    #[test]
+    fn removes_language_tags_without_markup() {
+        let result = format_line("[lang id=1031]Haus[/lang] [lang name=\"Old Norse\"]hús[/lang]", &get_no_markup_settings());
 
-pub fn format_entries(lines: Vec<String>, settings: HarlawSettings) -> Vec<DictionaryEntry> {
-    let mut dictionary_entries: Vec<DictionaryEntry> = vec![];
-    let mut index = 0;
+        assert_eq!(result, "Haus hús");
+    }
 
-    for (line_index, line) in lines.iter().enumerate() {
-        if is_empty_line(line) {
-            continue;
-        }
+    #[test]
+    fn keeps_tags_when_asked() {
+        let settings = HarlawSettings {
+            colors: ColorMarkup::Keep,
+            ..get_no_markup_settings()
+        };
 
-        let first_character = line.chars().next().unwrap().to_string();        
+        let result = format_line("[c red]foo[/c] [lang id=1031]Haus[/lang]", &settings);
 
-        // Skip metadata lines.
-        if is_metadata_line(&first_character) {
-            continue;
-        }
+        assert_eq!(result, "[c red]foo[/c] Haus");
+    }
 
-        // If line startes with starting character, it is definition of previous entry.
-        if is_definition_line(&first_character) {
-            let formatted_line = format_line(line, &settings);
-            dictionary_entries[index -1].definitions.push(formatted_line);
-            continue;
-        }
+    #[test]
+    fn formats_media_as_html() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1][s]hus.wav[/s] [s]hus.bmp[/s] house[/m]"),
+        ];
+        let settings = HarlawSettings {
+            media: MediaMarkup::Html(MediaUrls {
+                sound: "/sounds/",
+                ..Default::default()
+            }),
+            ..get_default_settings()
+        };
 
-        /*
-         * DSL files may contain many headwords in a row.
-         * This means the headwords use identical definition, which comes after them.
-         * Loop deeper to get the definition, if it is not present immediartely.
-         */
-        if !dictionary_entries.is_empty() && dictionary_entries[index - 1].definitions.is_empty() {
-            let mut not_found = true;
-            let mut lower_index = line_index;
+        let result = format_entries(lines, settings).unwrap();
 
-            while not_found {
-                lower_index += 1;
+        assert_eq!(result[0].definitions[0].text, "<audio controls src=\"/sounds/hus.wav\"></audio> <img src=\"hus.bmp\" alt=\"\"> house");
+        assert_eq!(result[0].media, vec![
+            MediaRef { file: String::from("hus.wav"), kind: MediaKind::Sound, location: None },
+            MediaRef { file: String::from("hus.bmp"), kind: MediaKind::Image, location: None },
+        ]);
+    }
 
-                let start_character = lines[lower_index].chars().next().unwrap().to_string();
-                if start_character.eq(TAB) {
-                    not_found = false;
-                    let formatted_line = format_line(&lines[lower_index], &settings);
-                    dictionary_entries[index - 1].definitions.push(formatted_line);
-                }
-            }
-        }
+    #[test]
+    fn removes_media_with_file_names_without_markup() {
+        let result = format_line("[s]hus.wav[/s] house", &get_no_markup_settings());
 
-        // The line is a headword, form a new entry. 
-        dictionary_entries.push(DictionaryEntry {
-            word: format_line(line, &settings),
-            definitions: vec![],
-        });
-        index += 1;
+        assert_eq!(result, "house");
     }
 
+    #[test]
+    fn applies_secondary_zone_policy() {
+        let line = "[m1]house[*] (also [i]home[/i])[/*][/m]";
+        let with_policy = |secondary| HarlawSettings {
+            secondary,
+            ..get_default_settings()
+        };
 
-    dictionary_entries
-}
+        assert_eq!(format_line(line, &with_policy(SecondaryZones::Wrap)), "house<span class=\"secondary\"> (also <i>home</i>)</span>");
+        assert_eq!(format_line(line, &with_policy(SecondaryZones::Inline)), "house (also <i>home</i>)");
+        assert_eq!(format_line(line, &with_policy(SecondaryZones::Drop)), "house");
+        assert_eq!(format_line(line, &with_policy(SecondaryZones::Keep)), "house[*] (also <i>home</i>)[/*]");
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::settings::get_default_settings;
+    #[test]
+    fn leaves_out_dropped_secondary_definitions() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1]house[/m]"),
+            String::from("	[m2][*][ex]stórt hús[/ex][/*][/m]"),
+            String::from("	[m1]home[/m]"),
+        ];
+        let settings = HarlawSettings {
+            secondary: SecondaryZones::Drop,
+            ..get_default_settings()
+        };
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result[0].definitions, vec![
+            Definition { level: 1, text: String::from("house") },
+            Definition { level: 1, text: String::from("home") },
+        ]);
+    }
 
     #[test]
-    fn formats_line() {
-        let line = "	[m1]Lorem ipsum [b]dolor[/b] sit amet, dolor sit igitur[/m]";        
-        
-        let result = format_line(line, &get_default_settings());
+    fn collects_nothing_from_dropped_secondary_zones() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1][p]n.[/p] house[*], see [ref]heimili[/ref] [s]hus.wav[/s][/*][/m]"),
+            String::from("	[m2][*][ex][p]pl.[/p] stórt hús[/ex][/*][/m]"),
+        ];
+        let settings = HarlawSettings {
+            secondary: SecondaryZones::Drop,
+            extract_zones: true,
+            ..get_default_settings()
+        };
 
-        assert_eq!(result, String::from("Lorem ipsum <strong>dolor</strong> sit amet, dolor sit igitur"));
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(texts(&result[0].definitions), vec!["<span>n.</span> house"]);
+        assert!(result[0].references.is_empty());
+        assert!(result[0].media.is_empty());
+        assert!(result[0].examples.is_empty());
+        assert_eq!(result[0].labels, vec![Label { abbreviation: String::from("n."), expansion: None }]);
+    }
+
+    #[test]
+    fn continues_secondary_zone_on_next_line() {
+        let lines = vec![
+            String::from("hús"),
+            String::from("	[m1]house [*](also[/m]"),
+            String::from("	[m2][i]home[/i])[/*] dwelling[/m]"),
+        ];
+        let with_policy = |secondary| HarlawSettings {
+            secondary,
+            ..get_default_settings()
+        };
+
+        let wrapped = format_entries(lines.clone(), with_policy(SecondaryZones::Wrap)).unwrap();
+        let inlined = format_entries(lines.clone(), with_policy(SecondaryZones::Inline)).unwrap();
+        let dropped = format_entries(lines, with_policy(SecondaryZones::Drop)).unwrap();
+
+        assert_eq!(texts(&wrapped[0].definitions), vec![
+            "house <span class=\"secondary\">(also</span>",
+            "<span class=\"secondary\"><i>home</i>)</span> dwelling",
+        ]);
+        assert_eq!(texts(&inlined[0].definitions), vec!["house (also", "<i>home</i>) dwelling"]);
+        assert_eq!(texts(&dropped[0].definitions), vec!["house", "dwelling"]);
+    }
+
+    #[test]
+    fn strips_comments() {
+        let lines = vec![
+            String::from("{{Checked}}foo"),
+            String::from("	[m1]Lorem {{fix this}}ipsum \\{\\{not a comment\\}\\}[/m]"),
+            String::from("	[m1]Dolor {{spans"),
+            String::from("	multiple lines}} sit amet[/m]"),
+            String::from("{{Comment only line}}"),
+            String::from("bar"),
+            String::from("	[m1]Consectetur[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].word, "foo");
+        assert_eq!(texts(&result[0].definitions), vec!["Lorem ipsum {{not a comment}}", "Dolor sit amet"]);
+        assert!(result[0].notes.is_empty());
+    }
+
+    #[test]
+    fn collapses_whitespace_around_comments() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	[m1]x {{a}} y[/m]"),
+            String::from("	[m1]x {{a"),
+            String::from("	b}} y[/m]"),
+            String::from("	{{a}} [m1]x {{b}}{{c}} y[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(texts(&result[0].definitions), vec!["x y", "x y", "x y"]);
+    }
+
+    #[test]
+    fn errors_on_unterminated_comment() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m] {{never closed"),
+            String::from("bar"),
+            String::from("#INCLUDE \"part2.dsl\""),
+            String::from("	[m1]Dolor sit amet[/m]"),
+        ];
+
+        let result = format_entries_with_includes(lines, get_default_settings(), |_include| panic!("include inside comment"));
+
+        assert!(matches!(result, Err(HarlawError::UnterminatedComment { line: 2 })));
+    }
+
+    #[test]
+    fn reports_unterminated_comment_leniently() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m] {{never closed"),
+            String::from("bar"),
+            String::from("	[m1]Dolor sit amet[/m]"),
+        ];
+
+        let (entries, diagnostics) = format_entries_leniently(lines, get_default_settings(), |_include| None);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(texts(&entries[0].definitions), vec!["Lorem ipsum"]);
+        assert_eq!(diagnostics, vec![Diagnostic {
+            line: 2,
+            text: String::from("{{never closed"),
+            category: DiagnosticCategory::UnterminatedComment,
+        }]);
+    }
+
+    #[test]
+    fn reports_include_leniently() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+            String::from("#INCLUDE	\"part2.dsl\""),
+            String::from("bar"),
+            String::from("	[m1]Dolor sit amet[/m]"),
+        ];
+
+        let (entries, diagnostics) = format_entries_leniently(lines, get_default_settings(), |_include| None);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(diagnostics, vec![Diagnostic {
+            line: 3,
+            text: String::from("#INCLUDE	\"part2.dsl\""),
+            category: DiagnosticCategory::UnresolvedInclude,
+        }]);
+    }
+
+    #[test]
+    fn keeps_comments_as_notes() {
+        let lines = vec![
+            String::from("{{Checked}}foo"),
+            String::from("	[m1]Lorem {{fix this}}ipsum {{spans"),
+            String::from("	lines}}[/m]"),
+            String::from("bar"),
+            String::from("	[m1]Consectetur[/m]"),
+        ];
+        let settings = HarlawSettings {
+            keep_notes: true,
+            ..get_default_settings()
+        };
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result[0].notes, vec!["Checked", "fix this", "spans\n\tlines"]);
+        assert!(result[1].notes.is_empty());
+    }
+
+    #[test]
+    fn nests_subentries() {
+        let lines = vec![
+            String::from("go"),
+            String::from("	[m1]to move[/m]"),
+            String::from("	@ go away"),
+            String::from("	[m1]to leave[/m]"),
+            String::from("	@ ~ on"),
+            String::from("	[m1]to continue[/m]"),
+            String::from("	[m2]~ on and on[/m]"),
+            String::from("	@"),
+            String::from("	[m1]to work, eg. \\@home[/m]"),
+            String::from("bar"),
+            String::from("	[m1]Lorem[/m]"),
+        ];
+        let settings = HarlawSettings {
+            substitute_tilde: true,
+            ..get_default_settings()
+        };
+
+        let result = format_entries(lines, settings).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(texts(&result[0].definitions), vec!["to move", "to work, eg. @home"]);
+        assert_eq!(result[0].subentries.len(), 2);
+        assert_eq!(result[0].subentries[0].word, "go away");
+        assert_eq!(texts(&result[0].subentries[0].definitions), vec!["to leave"]);
+        assert_eq!(result[0].subentries[1].word, "go on");
+        assert_eq!(result[0].subentries[1].definitions, vec![
+            Definition { level: 1, text: String::from("to continue") },
+            Definition { level: 2, text: String::from("go on and on") },
+        ]);
+        assert!(result[1].subentries.is_empty());
+    }
+
+    #[test]
+    fn flattens_subentries() {
+        let lines = vec![
+            String::from("go"),
+            String::from("walk"),
+            String::from("	[m1]to move[/m]"),
+            String::from("	@ go away"),
+            String::from("	[m1]to leave[/m]"),
+        ];
+        let settings = HarlawSettings {
+            flatten_subentries: true,
+            ..get_default_settings()
+        };
+
+        let result = format_entries(lines, settings).unwrap();
+
+        let words: Vec<&str> = result.iter().map(|entry| entry.word.as_str()).collect();
+        assert_eq!(words, vec!["go", "go away", "walk"]);
+        assert!(result[0].subentries.is_empty());
+        assert_eq!(result[1].keys, vec!["go away"]);
+    }
+
+    #[test]
+    fn completes_card_with_only_subentries() {
+        let lines = vec![
+            String::from("go"),
+            String::from("	@ go away"),
+            String::from("	[m1]to leave[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert!(result[0].definitions.is_empty());
+        assert_eq!(result[0].subentries[0].word, "go away");
+    }
+
+    #[test]
+    fn expands_headword_keys() {
+        let lines = vec![
+            String::from("abbindi(s)"),
+            String::from("{to }go"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result[0].word, "abbindi(s)");
+        assert_eq!(result[0].keys, vec!["abbindi", "abbindis"]);
+        assert_eq!(result[1].word, "to go");
+        assert_eq!(result[1].keys, vec!["go"]);
     }
 
     #[test]
@@ -109,10 +1584,10 @@ mod tests {
             String::from("	[m1]Lorem ipsum dolor sit amet, dolor sit igitur[/m]")
         ];
         
-        let result = format_entries(lines, get_default_settings());
+        let result = format_entries(lines, get_default_settings()).unwrap();
 
         assert_eq!(result[0].word, "foo");
-        assert_eq!(result[0].definitions[0], "Lorem ipsum dolor sit amet, dolor sit igitur");
+        assert_eq!(result[0].definitions[0].text, "Lorem ipsum dolor sit amet, dolor sit igitur");
     }
 
     #[test]
@@ -125,12 +1600,12 @@ mod tests {
             String::from(" [m1]Dolor sit amet[/m]")
         ];
         
-        let result = format_entries(lines, get_default_settings());
+        let result = format_entries(lines, get_default_settings()).unwrap();
 
         assert_eq!(result[0].word, "foo");
-        assert_eq!(result[0].definitions[0], "Lorem ipsum dolor sit amet, dolor sit igitur");
+        assert_eq!(result[0].definitions[0].text, "Lorem ipsum dolor sit amet, dolor sit igitur");
         assert_eq!(result[1].word, "bar");
-        assert_eq!(result[1].definitions[0], "Dolor sit amet");
+        assert_eq!(result[1].definitions[0].text, "Dolor sit amet");
     }
 
     #[test]
@@ -143,10 +1618,23 @@ mod tests {
             String::from("    [m1]Lorem ipsum dolor sit amet, dolor sit igitur[/m]"),
         ];
         
-        let result = format_entries(lines, get_default_settings());
+        let result = format_entries(lines, get_default_settings()).unwrap();
 
         assert_eq!(result[0].word, "foo");
-        assert_eq!(result[0].definitions[0], "Lorem ipsum dolor sit amet, dolor sit igitur");
+        assert_eq!(result[0].definitions[0].text, "Lorem ipsum dolor sit amet, dolor sit igitur");
+    }
+
+    #[test]
+    fn errors_on_definition_before_headword() {
+        let lines = vec![
+            String::from("#NAME	\"Test Dictionary fixture\""),
+            String::from("	[m1]Lorem ipsum dolor sit amet[/m]"),
+            String::from("foo"),
+        ];
+
+        let result = format_entries(lines, get_default_settings());
+
+        assert!(matches!(result, Err(HarlawError::OrphanDefinition { line: 2 })));
     }
 
     #[test]
@@ -163,20 +1651,176 @@ mod tests {
             String::from("	[m2]Lorem ipsum dolor sit amet.[/m]"),
         ];
         
-        let result = format_entries(lines, get_default_settings());
+        let result = format_entries(lines, get_default_settings()).unwrap();
 
         assert_eq!(result[0].word, "foo");
-        assert_eq!(result[0].definitions[0], "Lorem ipsum dolor sit amet, dolor sit igitur");
+        assert_eq!(result[0].definitions[0].text, "Lorem ipsum dolor sit amet, dolor sit igitur");
 
         assert_eq!(result[1].word, "bar");
-        assert_eq!(result[1].definitions[0], "<strong>Dolor</strong> sit igitur.");
+        assert_eq!(result[1].definitions[0].text, "<strong>Dolor</strong> sit igitur.");
 
         assert_eq!(result[2].word, "bar-like-word-with-same-defs");
-        assert_eq!(result[2].definitions[0], "<strong>Dolor</strong> sit igitur.");
+        assert_eq!(result[2].definitions[0].text, "<strong>Dolor</strong> sit igitur.");
+        assert_eq!(result[1].definitions, result[2].definitions);
 
 
         assert_eq!(result[3].word, "baz");
-        assert_eq!(result[3].definitions[0], "Lorem ipsum dolor sit amet, consectetur adipiscing elit");
-        assert_eq!(result[3].definitions[1], "Lorem ipsum dolor sit amet.");
+        assert_eq!(result[3].definitions[0].text, "Lorem ipsum dolor sit amet, consectetur adipiscing elit");
+        assert_eq!(result[3].definitions[1].text, "Lorem ipsum dolor sit amet.");
+    }
+
+    #[test]
+    fn shares_all_definitions_within_headword_group() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("foo-like-word"),
+            String::from(""),
+            String::from("	[m1]Lorem ipsum[/m]"),
+            String::from("	[m2]Dolor sit amet[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(texts(&result[0].definitions), vec!["Lorem ipsum", "Dolor sit amet"]);
+        assert_eq!(texts(&result[1].definitions), vec!["Lorem ipsum", "Dolor sit amet"]);
+    }
+
+    #[test]
+    fn finds_grouped_definitions_indented_with_spaces() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("foo-like-word"),
+            String::from("  [m1]Lorem ipsum[/m]"),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result[0].definitions[0].text, "Lorem ipsum");
+        assert_eq!(result[1].definitions[0].text, "Lorem ipsum");
+    }
+
+    #[test]
+    fn errors_on_trailing_headwords_without_definition() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+            String::from("bar"),
+            String::from("bar-like-word"),
+            String::from(""),
+        ];
+
+        let result = format_entries(lines, get_default_settings());
+
+        assert!(matches!(result, Err(HarlawError::MissingDefinition { headword, line: 3 }) if headword == "bar"));
+    }
+
+    #[test]
+    fn formats_empty_input_to_no_entries() {
+        let lines = vec![
+            String::from("#NAME	\"Test Dictionary fixture\""),
+            String::from(""),
+        ];
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn formats_entries_leniently() {
+        let lines = vec![
+            String::from("	[m1]Orphan[/m]"),
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+            String::from("bar"),
+        ];
+
+        let (entries, diagnostics) = format_entries_leniently(lines, get_default_settings(), |_include| None);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].word, "foo");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].category, DiagnosticCategory::OrphanDefinition);
+        assert_eq!(diagnostics[0].line, 1);
+        assert_eq!(diagnostics[1].category, DiagnosticCategory::MissingDefinition);
+        assert_eq!(diagnostics[1].text, "bar");
+        assert_eq!(diagnostics[1].line, 4);
+    }
+
+    #[test]
+    fn keeps_every_headword_of_large_group() {
+        let mut lines: Vec<String> = (0..300).map(|index| format!("word{}", index)).collect();
+        lines.push(String::from("	[m1]Lorem ipsum[/m]"));
+
+        let result = format_entries(lines, get_default_settings()).unwrap();
+
+        assert_eq!(result.len(), 300);
+        assert_eq!(result[0].word, "word0");
+        assert_eq!(result[299].definitions[0].text, "Lorem ipsum");
+    }
+
+    #[test]
+    fn splices_included_entries_in_place() {
+        let lines = vec![
+            String::from("#NAME	\"Test Dictionary fixture\""),
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+            String::from("#INCLUDE	\"part2.dsl\""),
+            String::from("bar"),
+            String::from("	[m1]Dolor sit amet[/m]"),
+        ];
+
+        let result = format_entries_with_includes(lines, get_default_settings(), |include| {
+            assert_eq!(include, "part2.dsl");
+
+            Ok(vec![DictionaryEntry {
+                word: String::from("included"),
+                definitions: vec![Definition { level: 1, text: String::from("From part 2") }],
+                ..Default::default()
+            }])
+        }).unwrap();
+
+        let words: Vec<&str> = result.iter().map(|entry| entry.word.as_str()).collect();
+        assert_eq!(words, vec!["foo", "included", "bar"]);
+    }
+
+    #[test]
+    fn completes_card_before_included_entries() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	[m1]Lorem ipsum[/m]"),
+            String::from("bar"),
+            String::from("#INCLUDE	\"part2.dsl\""),
+            String::from("	[m1]Dolor sit amet[/m]"),
+        ];
+
+        let result = format_entries_with_includes(lines, get_default_settings(), |_include| Ok(vec![]));
+
+        assert!(matches!(result, Err(HarlawError::MissingDefinition { headword, line: 3 }) if headword == "bar"));
+    }
+
+    #[test]
+    fn ignores_indented_include() {
+        let lines = vec![
+            String::from("foo"),
+            String::from("	#INCLUDE \"part2.dsl\""),
+        ];
+
+        let result = format_entries_with_includes(lines, get_default_settings(), |_include| panic!("indented include")).unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].word, "foo");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn passes_include_errors_through() {
+        let lines = vec![
+            String::from("#INCLUDE \"missing.dsl\""),
+        ];
+
+        let result = format_entries_with_includes(lines, get_default_settings(), |_include| Err(HarlawError::NotDsl));
+
+        assert!(matches!(result, Err(HarlawError::NotDsl)));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::markup::{characters, parse_markup, markup_to_text, Character};
+
+const OPTIONAL_START: char = '(';
+const OPTIONAL_END: char = ')';
+const UNSORTED_START: char = '{';
+const UNSORTED_END: char = '}';
+
+// Each optional part doubles the number of keys, so expand only so many of them.
+const MAX_OPTIONAL_PARTS: usize = 5;
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Optional(String),
+    Unsorted(String),
+}
+
+/*
+ * Splits headword to plain text, (optional) and {unsorted} parts.
+ * Escaped characters are kept escaped, they are unescaped along with
+ * the rest of the markup later on.
+ */
+fn parse_segments(headword: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut current = String::new();
+    let mut closing: Option<char> = None;
+
+    for character in characters(headword) {
+        let character = match character {
+            Character::Plain(character) => character,
+            escaped => {
+                escaped.push_dsl(&mut current);
+                continue;
+            },
+        };
+
+        match (character, closing) {
+            (OPTIONAL_START, None) | (UNSORTED_START, None) => {
+                if !current.is_empty() {
+                    segments.push(Segment::Text(current.clone()));
+                    current.clear();
+                }
+                closing = Some(if character == OPTIONAL_START { OPTIONAL_END } else { UNSORTED_END });
+            },
+            (character, Some(end)) if character == end => {
+                let content = current.clone();
+                current.clear();
+                closing = None;
+
+                segments.push(match end {
+                    OPTIONAL_END => Segment::Optional(content),
+                    _ => Segment::Unsorted(content),
+                });
+            },
+            _ => current.push(character),
+        }
+    }
+
+    // Unclosed part is taken literally.
+    if let Some(end) = closing {
+        let start = if end == OPTIONAL_END { OPTIONAL_START } else { UNSORTED_START };
+        current.insert(0, start);
+    }
+
+    if !current.is_empty() {
+        segments.push(Segment::Text(current));
+    }
+
+    segments
+}
+
+// Unsorted parts may also appear inside optional ones: (to {be})
+fn remove_unsorted(content: &str) -> String {
+    parse_segments(content)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text,
+            Segment::Optional(optional) => format!("{}{}{}", OPTIONAL_START, optional, OPTIONAL_END),
+            Segment::Unsorted(_) => String::new(),
+        })
+        .collect()
+}
+
+fn to_key(dsl: &str) -> String {
+    let text = markup_to_text(&parse_markup(dsl));
+
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Headword as displayed: braces of unsorted parts are removed, optional parts are kept as is.
+/// Result is still DSL markup, to be formatted like the rest of the entry.
+pub fn headword_display(headword: &str) -> String {
+    parse_segments(headword)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) | Segment::Unsorted(text) => text,
+            Segment::Optional(optional) => format!("{}{}{}", OPTIONAL_START, optional, OPTIONAL_END),
+        })
+        .collect()
+}
+
+/// Headword as substituted for `~` in definitions: unsorted parts are left out.
+/// Result is still DSL markup, to be formatted like the rest of the entry.
+pub fn headword_substitute(headword: &str) -> String {
+    let substitute: String = parse_segments(headword)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text,
+            Segment::Optional(optional) => format!("{}{}{}", OPTIONAL_START, remove_unsorted(&optional), OPTIONAL_END),
+            Segment::Unsorted(_) => String::new(),
+        })
+        .collect();
+
+    substitute.trim().to_string()
+}
+
+/// Lookup keys of headword, as plain text: unsorted parts are left out,
+/// and each optional part is expanded both without and with it.
+/// `abbindi(s)` gives `abbindi` and `abbindis`, while `{to }go` gives `go`.
+pub fn headword_keys(headword: &str) -> Vec<String> {
+    let segments = parse_segments(headword);
+    let optional_count = segments
+        .iter()
+        .filter(|segment| matches!(segment, Segment::Optional(_)))
+        .count();
+
+    // With too many optional parts, expand only all-out and all-in variants.
+    let variants: Vec<usize> = if optional_count <= MAX_OPTIONAL_PARTS {
+        (0..1 << optional_count).collect()
+    } else {
+        vec![0, usize::MAX]
+    };
+
+    let mut keys: Vec<String> = vec![];
+
+    for variant in variants {
+        let mut optional_index = 0;
+        let mut key = String::new();
+
+        for segment in &segments {
+            match segment {
+                Segment::Text(text) => key.push_str(text),
+                Segment::Unsorted(_) => {},
+                Segment::Optional(optional) => {
+                    if variant & (1 << optional_index.min(usize::BITS as usize - 1)) != 0 {
+                        key.push_str(&remove_unsorted(optional));
+                    }
+                    optional_index += 1;
+                },
+            }
+        }
+
+        let key = to_key(&key);
+
+        if !key.is_empty() && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_optional_parts() {
+        assert_eq!(headword_keys("abbindi(s)"), vec!["abbindi", "abbindis"]);
+        assert_eq!(headword_keys("(ó)hreinn(a)"), vec!["hreinn", "óhreinn", "hreinna", "óhreinna"]);
+    }
+
+    #[test]
+    fn leaves_out_unsorted_parts() {
+        assert_eq!(headword_keys("{to }go"), vec!["go"]);
+        assert_eq!(headword_keys("go {(to)}"), vec!["go"]);
+        assert_eq!(headword_keys("(to {be}) go"), vec!["go", "to go"]);
+    }
+
+    #[test]
+    fn keeps_escaped_characters_literal() {
+        assert_eq!(headword_keys("C\\(k\\) \\{x\\}"), vec!["C(k) {x}"]);
+        assert_eq!(headword_display("C\\(k\\)"), "C\\(k\\)");
+    }
+
+    #[test]
+    fn strips_markup_from_keys() {
+        assert_eq!(headword_keys("dr[']a[/']kon"), vec!["drakon"]);
+    }
+
+    #[test]
+    fn limits_expanded_optional_parts() {
+        let keys = headword_keys("a(b)c(d)e(f)g(h)i(j)k(l)");
+
+        assert_eq!(keys, vec!["acegik", "abcdefghijkl"]);
+    }
+
+    #[test]
+    fn substitutes_headword_without_unsorted_parts() {
+        assert_eq!(headword_substitute("{to }go"), "go");
+        assert_eq!(headword_substitute("abbindi(s)"), "abbindi(s)");
+    }
+
+    #[test]
+    fn displays_headword_without_unsorted_braces() {
+        assert_eq!(headword_display("{to }go"), "to go");
+        assert_eq!(headword_display("abbindi(s)"), "abbindi(s)");
+        assert_eq!(headword_display("foo (bar"), "foo (bar");
+    }
+}
@@ -0,0 +1,110 @@
+use crate::markup::Attribute;
+
+const ID: &str = "id";
+const NAME: &str = "name";
+
+// Lingvo language ids (Windows LCIDs) and names, with their BCP-47 codes.
+const LANGUAGES: &[(u32, &str, &str)] = &[
+    (1025, "Arabic", "ar"),
+    (1026, "Bulgarian", "bg"),
+    (1027, "Catalan", "ca"),
+    (1028, "ChineseTraditional", "zh-TW"),
+    (1029, "Czech", "cs"),
+    (1030, "Danish", "da"),
+    (1031, "German", "de"),
+    (1032, "Greek", "el"),
+    (1033, "English", "en"),
+    (1034, "Spanish", "es"),
+    (1035, "Finnish", "fi"),
+    (1036, "French", "fr"),
+    (1037, "Hebrew", "he"),
+    (1038, "Hungarian", "hu"),
+    (1039, "Icelandic", "is"),
+    (1040, "Italian", "it"),
+    (1041, "Japanese", "ja"),
+    (1042, "Korean", "ko"),
+    (1043, "Dutch", "nl"),
+    (1044, "Norwegian", "nb"),
+    (1045, "Polish", "pl"),
+    (1046, "PortugueseBrazilian", "pt-BR"),
+    (1048, "Romanian", "ro"),
+    (1049, "Russian", "ru"),
+    (1050, "Croatian", "hr"),
+    (1051, "Slovak", "sk"),
+    (1052, "Albanian", "sq"),
+    (1053, "Swedish", "sv"),
+    (1054, "Thai", "th"),
+    (1055, "Turkish", "tr"),
+    (1057, "Indonesian", "id"),
+    (1058, "Ukrainian", "uk"),
+    (1059, "Belarusian", "be"),
+    (1060, "Slovenian", "sl"),
+    (1061, "Estonian", "et"),
+    (1062, "Latvian", "lv"),
+    (1063, "Lithuanian", "lt"),
+    (1066, "Vietnamese", "vi"),
+    (1067, "Armenian", "hy"),
+    (1068, "Azeri", "az"),
+    (1069, "Basque", "eu"),
+    (1071, "Macedonian", "mk"),
+    (1079, "Georgian", "ka"),
+    (1081, "Hindi", "hi"),
+    (1086, "Malay", "ms"),
+    (1087, "Kazakh", "kk"),
+    (1088, "Kyrgyz", "ky"),
+    (1091, "Uzbek", "uz"),
+    (1092, "Tatar", "tt"),
+    (1106, "Welsh", "cy"),
+    (2052, "ChineseSimplified", "zh-CN"),
+    (2070, "Portuguese", "pt"),
+    (3098, "Serbian", "sr"),
+];
+
+/// BCP-47 code of `[lang]` tag attributes, eg. "de" for `id=1031` or `name="German"`.
+/// Names are matched case-insensitively, ignoring spaces. Unknown languages have no code.
+pub fn language_code(attributes: &[Attribute]) -> Option<&'static str> {
+    attributes.iter().find_map(|attribute| {
+        let value = attribute.value.as_deref()?;
+
+        match attribute.name.as_str() {
+            ID => {
+                let id: u32 = value.trim().parse().ok()?;
+                LANGUAGES.iter().find(|(language_id, _, _)| *language_id == id)
+            },
+            NAME => {
+                let name: String = value.split_whitespace().collect();
+                LANGUAGES.iter().find(|(_, language_name, _)| language_name.eq_ignore_ascii_case(&name))
+            },
+            _ => None,
+        }
+        .map(|(_, _, code)| *code)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(name: &str, value: &str) -> Attribute {
+        Attribute { name: String::from(name), value: Some(String::from(value)) }
+    }
+
+    #[test]
+    fn finds_language_by_id() {
+        assert_eq!(language_code(&[attribute("id", "1031")]), Some("de"));
+        assert_eq!(language_code(&[attribute("id", "2052")]), Some("zh-CN"));
+    }
+
+    #[test]
+    fn finds_language_by_name() {
+        assert_eq!(language_code(&[attribute("name", "German")]), Some("de"));
+        assert_eq!(language_code(&[attribute("name", "Chinese Simplified")]), Some("zh-CN"));
+    }
+
+    #[test]
+    fn has_no_code_for_unknown_language() {
+        assert_eq!(language_code(&[attribute("id", "9999")]), None);
+        assert_eq!(language_code(&[attribute("name", "Klingon")]), None);
+        assert_eq!(language_code(&[]), None);
+    }
+}
@@ -3,9 +3,21 @@ mod settings;
 mod writer;
 mod reader;
 mod formatter;
+mod error;
+mod stream;
+mod markup;
+mod headword;
+mod language;
+mod media;
 
-pub use dictionary::DictionaryEntry;
-pub use settings::{HarlawSettings, ContentReplace, get_default_settings, get_no_markup_settings};
+pub use dictionary::{Dictionary, DictionaryEntry, Definition, DictionaryMetadata, Diagnostic, DiagnosticCategory, ParseReport, BrokenReference, Label, MediaRef, MediaKind, MediaLocation};
+pub use error::HarlawError;
+pub use stream::EntryIter;
+pub use markup::{Node, Attribute, parse_markup, markup_to_dsl, markup_to_html, markup_to_text};
+pub use headword::{headword_display, headword_keys};
+pub use settings::{HarlawSettings, ContentReplace, ColorMarkup, LanguageMarkup, MediaMarkup, MediaUrls, SecondaryZones, get_default_settings, get_no_markup_settings};
+
+use std::io::{BufRead, Read};
 
 
 /// Transform DSL dictionary into vector of DictionaryEntries with default markup.
@@ -18,11 +30,11 @@ pub use settings::{HarlawSettings, ContentReplace, get_default_settings, get_no_
 /// 
 /// let my_dictionary = "./my-dictionary.dsl";
 /// 
-/// // Result either contains Vec<DictionaryEntry> or error message
+/// // Result either contains Vec<DictionaryEntry> or HarlawError
 /// let result = get_dictionary(my_dictionary);
 /// 
 /// ```
-pub fn get_dictionary(filepath: &str) -> Result<Vec<DictionaryEntry>, &'static str> {
+pub fn get_dictionary(filepath: &str) -> Result<Vec<DictionaryEntry>, HarlawError> {
     let settings = get_default_settings();
 
     dictionary::to_dictionary(filepath, settings)
@@ -38,11 +50,11 @@ pub fn get_dictionary(filepath: &str) -> Result<Vec<DictionaryEntry>, &'static s
 /// 
 /// let my_dictionary = "./my-dictionary.dsl";
 /// 
-/// // Result either contains Vec<DictionaryEntry> or error message
+/// // Result either contains Vec<DictionaryEntry> or HarlawError
 /// let result = get_dictionary_without_markup(my_dictionary);
 /// 
 /// ```
-pub fn get_dictionary_without_markup(filepath: &str) -> Result<Vec<DictionaryEntry>, &'static str> {
+pub fn get_dictionary_without_markup(filepath: &str) -> Result<Vec<DictionaryEntry>, HarlawError> {
     let settings = get_no_markup_settings();
 
     dictionary::to_dictionary(filepath, settings)
@@ -77,16 +89,195 @@ pub fn get_dictionary_without_markup(filepath: &str) -> Result<Vec<DictionaryEnt
 ///             replace: "</skew>",
 ///         }
 ///     ],
+///     ..Default::default()
 /// };
 /// 
-/// // Result either contains Vec<DictionaryEntry> or error message
+/// // Result either contains Vec<DictionaryEntry> or HarlawError
 /// let result = get_dictionary_with_custom_settings(my_dictionary, settings);
 /// 
 /// ```
-pub fn get_dictionary_with_custom_settings(filename: &str, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, &'static str> {
+pub fn get_dictionary_with_custom_settings(filename: &str, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, HarlawError> {
     dictionary::to_dictionary(filename, settings)
 }
 
+/// Transform DSL dictionary into vector of DictionaryEntries, skipping broken entries
+/// instead of failing. Skipped parts are reported as warnings, with line numbers.
+/// 
+/// Still fails if the file can not be read at all.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_dictionary_lenient, get_default_settings};
+/// 
+/// let my_dictionary = "./my-dictionary.dsl";
+/// 
+/// // Result either contains ParseReport or HarlawError
+/// let result = get_dictionary_lenient(my_dictionary, get_default_settings());
+/// 
+/// if let Ok(report) = result {
+///     for warning in report.warnings {
+///         println!("{:?} on line {}: {}", warning.category, warning.line, warning.text);
+///     }
+/// }
+/// 
+/// ```
+pub fn get_dictionary_lenient(filepath: &str, settings: HarlawSettings) -> Result<ParseReport, HarlawError> {
+    dictionary::to_dictionary_lenient(filepath, settings)
+}
+
+/// Read DSL dictionary lazily, one entry at a time. Suitable for very large dictionaries,
+/// as the whole dictionary is never held in memory.
+/// 
+/// Encoding is detected the same way as in other methods, and the content is transcoded to UTF-8 while reading.
+/// Undecodable characters are replaced with U+FFFD, except in UTF-8 files, where they are returned as errors.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_dictionary_iter, get_default_settings};
+/// 
+/// let my_dictionary = "./my-dictionary.dsl";
+/// 
+/// if let Ok(entries) = get_dictionary_iter(my_dictionary, get_default_settings()) {
+///     // Each item is either DictionaryEntry or HarlawError.
+///     for entry in entries.flatten() {
+///         println!("{}", entry.word);
+///     }
+/// }
+/// 
+/// ```
+pub fn get_dictionary_iter<'a>(filepath: &str, settings: HarlawSettings<'a>) -> Result<EntryIter<'a, Box<dyn BufRead>>, HarlawError> {
+    let settings = dictionary::with_abbreviations(filepath, settings)?;
+    let source = reader::open_dsl_stream(filepath, settings.encoding)?;
+
+    Ok(EntryIter::new(source, settings))
+}
+
+/// Transform DSL dictionary into entries along with dictionary metadata,
+/// such as name and languages from the DSL headers.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_dictionary_with_metadata, get_default_settings, Dictionary};
+/// 
+/// let my_dictionary = "./my-dictionary.dsl";
+/// 
+/// // Result either contains Dictionary or HarlawError
+/// let result = get_dictionary_with_metadata(my_dictionary, get_default_settings());
+/// 
+/// if let Ok(dictionary) = result {
+///     println!("{:?}", dictionary.metadata.name);
+/// }
+/// 
+/// ```
+pub fn get_dictionary_with_metadata(filepath: &str, settings: HarlawSettings) -> Result<Dictionary, HarlawError> {
+    dictionary::to_dictionary_with_metadata(filepath, settings)
+}
+
+/// Read dictionary metadata without the entries. Only the headers at the beginning of the file are read. Along with them,
+/// the description from `.ann` annotation file and the path of the icon next to dictionary are included.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_dictionary_metadata, get_default_settings};
+/// 
+/// let my_dictionary = "./my-dictionary.dsl";
+/// 
+/// // Result either contains DictionaryMetadata or HarlawError
+/// if let Ok(metadata) = get_dictionary_metadata(my_dictionary, get_default_settings()) {
+///     println!("{:?} {:?}", metadata.annotation, metadata.icon);
+/// }
+/// 
+/// ```
+pub fn get_dictionary_metadata(filepath: &str, settings: HarlawSettings) -> Result<DictionaryMetadata, HarlawError> {
+    dictionary::read_metadata(filepath, &settings)
+}
+
+/// Transform DSL content from a string into vector of DictionaryEntries.
+/// Useful for DSL content that does not come from a file, eg. embedded with `include_str!`.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_dictionary_from_str, get_default_settings};
+/// 
+/// let content = "#NAME\t\"My dictionary\"\nfoo\n\t[m1][i]bar[/i][/m]";
+/// 
+/// let result = get_dictionary_from_str(content, get_default_settings()).unwrap();
+/// 
+/// assert_eq!(result[0].word, "foo");
+/// assert_eq!(result[0].definitions[0].text, "<i>bar</i>");
+/// 
+/// ```
+pub fn get_dictionary_from_str(content: &str, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    dictionary::from_str(content, settings)
+}
+
+/// Transform DSL content from any Read source into vector of DictionaryEntries.
+/// Useful for uploads, archives and other content without a DSL filename.
+/// Encoding is detected the same way as with files.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_dictionary_from_reader, get_default_settings};
+/// 
+/// let upload: &[u8] = b"foo\n\t[m1][b]bar[/b][/m]\n";
+/// 
+/// let result = get_dictionary_from_reader(upload, get_default_settings()).unwrap();
+/// 
+/// assert_eq!(result[0].definitions[0].text, "<strong>bar</strong>");
+/// 
+/// ```
+pub fn get_dictionary_from_reader<R: Read>(source: R, settings: HarlawSettings) -> Result<Vec<DictionaryEntry>, HarlawError> {
+    dictionary::from_reader(source, settings)
+}
+
+/// Find references pointing at headwords that are not in the dictionary.
+/// References are matched case-insensitively against entry words and their lookup keys.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_dictionary_from_str, find_broken_references, get_default_settings};
+/// 
+/// let content = "foo\n\t[m1]See [ref]bar[/ref] and [ref]baz[/ref][/m]\nbar\n\t[m1]Lorem[/m]";
+/// let entries = get_dictionary_from_str(content, get_default_settings()).unwrap();
+/// 
+/// let broken = find_broken_references(&entries);
+/// 
+/// assert_eq!(broken[0].word, "foo");
+/// assert_eq!(broken[0].reference, "baz");
+/// 
+/// ```
+pub fn find_broken_references(entries: &[DictionaryEntry]) -> Vec<BrokenReference> {
+    dictionary::find_broken_references(entries)
+}
+
+/// Resolve media files of entries against the resources of the dictionary:
+/// `my-dictionary.dsl.files.zip` archive or `my-dictionary.dsl.files` directory next to it.
+/// Location is set for media files that are found. Dictionary without resources is fine.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_dictionary, resolve_media};
+/// 
+/// let my_dictionary = "./my-dictionary.dsl";
+/// 
+/// if let Ok(mut entries) = get_dictionary(my_dictionary) {
+///     // Result either contains () or HarlawError
+///     let result = resolve_media(my_dictionary, &mut entries);
+/// }
+/// 
+/// ```
+pub fn resolve_media(filepath: &str, entries: &mut [DictionaryEntry]) -> Result<(), HarlawError> {
+    media::resolve_media(filepath, entries)
+}
+
 /// Transform DSL dictionary into a JSON file.
 /// Default Lingvo tags are transformed to their HTML equilevants.
 /// 
@@ -98,11 +289,11 @@ pub fn get_dictionary_with_custom_settings(filename: &str, settings: HarlawSetti
 /// let input = "./my-dictionary.dsl"; // Original DSL dictionary.
 /// let output = "./my-dictionary.json"; // JSON version to be created.
 /// 
-/// // Result object with either Ok or Err message.
+/// // Result object with either Ok or HarlawError.
 /// let result = to_json(input, output);
 /// 
 /// ```
-pub fn to_json(input: &str, output: &str) -> Result<(), &'static str> {
+pub fn to_json(input: &str, output: &str) -> Result<(), HarlawError> {
     let settings = get_default_settings();
 
     dictionary::to_json(input, output, settings)
@@ -120,11 +311,11 @@ pub fn to_json(input: &str, output: &str) -> Result<(), &'static str> {
 /// let input = "./my-dictionary.dsl"; // Original DSL dictionary.
 /// let output = "./my-dictionary.json"; // JSON version to be created.
 /// 
-/// // Result object with either Ok or Err message.
+/// // Result object with either Ok or HarlawError.
 /// let result = to_json_no_markup(input, output);
 /// 
 /// ```
-pub fn to_json_no_markup(input: &str, output: &str) -> Result<(), &'static str> {
+pub fn to_json_no_markup(input: &str, output: &str) -> Result<(), HarlawError> {
     let settings = get_no_markup_settings();
 
     dictionary::to_json(input, output, settings)
@@ -160,12 +351,32 @@ pub fn to_json_no_markup(input: &str, output: &str) -> Result<(), &'static str>
 ///             replace: "</skew>",
 ///         }
 ///     ],
+///     ..Default::default()
 /// };
 /// 
-/// // Result object with either Ok or Err message.
+/// // Result object with either Ok or HarlawError.
 /// let result = to_json_with_custom_settings(input, output, settings);
 /// 
 /// ```
-pub fn to_json_with_custom_settings(input: &str, output: &str, settings: HarlawSettings) -> Result<(), &'static str> {
+pub fn to_json_with_custom_settings(input: &str, output: &str, settings: HarlawSettings) -> Result<(), HarlawError> {
     dictionary::to_json(input, output, settings)
-}
\ No newline at end of file
+}
+
+/// Transform DSL dictionary into a JSON file, including dictionary metadata.
+/// Instead of plain list of entries, the JSON is an object with "metadata" and "entries" keys.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{to_json_with_metadata, get_default_settings};
+/// 
+/// let input = "./my-dictionary.dsl";
+/// let output = "./my-dictionary.json";
+/// 
+/// // Result object with either Ok or HarlawError.
+/// let result = to_json_with_metadata(input, output, get_default_settings());
+/// 
+/// ```
+pub fn to_json_with_metadata(input: &str, output: &str, settings: HarlawSettings) -> Result<(), HarlawError> {
+    dictionary::to_json_with_metadata(input, output, settings)
+}
@@ -0,0 +1,730 @@
+use serde::{Deserialize, Serialize};
+use crate::formatter;
+
+const ESCAPE: char = '\\';
+const TAG_START: char = '[';
+const TAG_END: char = ']';
+const CLOSING: char = '/';
+// Characters with meaning in DSL: markup, tilde, comments, subentries, directives and capitalisation.
+const SPECIAL_CHARACTERS: &[char] = &[ESCAPE, TAG_START, TAG_END, '~', '{', '}', '@', '#', '^'];
+const MARGIN: &str = "m";
+const REFERENCE: &str = "ref";
+const LABEL: &str = "p";
+const MEDIA: &str = "s";
+const REFERENCE_START: &str = "<<";
+const REFERENCE_END: &str = ">>";
+
+/// Attribute of DSL tag.
+/// `[lang id=1033]` has attribute with name "id" and value "1033",
+/// while `[c red]` has attribute with name "red" and no value.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// Node of parsed DSL markup. Text is always unescaped.
+///
+/// # Examples
+///
+/// ```
+/// use harlaw::{parse_markup, Node};
+///
+/// let nodes = parse_markup("[b]foo[/b] bar");
+///
+/// assert_eq!(nodes[0], Node::Tag {
+///     name: String::from("b"),
+///     attrs: vec![],
+///     children: vec![Node::Text(String::from("foo"))],
+/// });
+/// assert_eq!(nodes[1], Node::Text(String::from(" bar")));
+/// ```
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Node {
+    Text(String),
+    Tag {
+        name: String,
+        attrs: Vec<Attribute>,
+        children: Vec<Node>,
+    },
+}
+
+/*
+ * Piece of DSL line. Escaped characters are kept apart from text,
+ * so that they are never mistaken for markup, comments or tildes.
+ */
+pub enum Token {
+    Text(String),
+    Escaped(char),
+    Open { name: String, attrs: Vec<Attribute>, raw: String },
+    Close { name: String, raw: String },
+}
+
+impl Token {
+    // Token as it was written in DSL.
+    pub fn to_dsl(&self) -> String {
+        match self {
+            Token::Text(text) => text.clone(),
+            Token::Escaped(character) => format!("{}{}", ESCAPE, character),
+            Token::Open { raw, .. } | Token::Close { raw, .. } => raw.clone(),
+        }
+    }
+}
+
+/*
+ * Parsed DSL markup, like Node, but with escaped characters kept apart from text.
+ * Formatting uses this to transform text without touching escaped characters.
+ * Tags keep their DSL form, for settings that match tags as written.
+ */
+pub enum Markup {
+    Text(String),
+    Escaped(char),
+    // Closing tag without opening tag.
+    Close { name: String, raw: String },
+    Tag {
+        name: String,
+        attrs: Vec<Attribute>,
+        raw: String,
+        children: Vec<Markup>,
+    },
+}
+
+struct OpenTag {
+    name: String,
+    attrs: Vec<Attribute>,
+    raw: String,
+    children: Vec<Markup>,
+    // Tag split by overlapping tag is opened again after it.
+    reopened: bool,
+}
+
+fn push_text(nodes: &mut Vec<Markup>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    match nodes.last_mut() {
+        Some(Markup::Text(previous)) => previous.push_str(text),
+        _ => nodes.push(Markup::Text(text.to_string())),
+    }
+}
+
+fn push_text_token(tokens: &mut Vec<Token>, character: char) {
+    match tokens.last_mut() {
+        Some(Token::Text(previous)) => previous.push(character),
+        _ => tokens.push(Token::Text(character.to_string())),
+    }
+}
+
+// Splits attributes by whitespace, keeping quoted values together: id=1 name="Old Norse" red
+pub fn parse_attributes(content: &str) -> Vec<Attribute> {
+    let mut attributes = vec![];
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for character in content.chars().chain(std::iter::once(' ')) {
+        match character {
+            '"' => in_quotes = !in_quotes,
+            character if character.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    let attribute = match current.split_once('=') {
+                        Some((name, value)) => Attribute { name: name.to_string(), value: Some(value.to_string()) },
+                        None => Attribute { name: current.clone(), value: None },
+                    };
+                    attributes.push(attribute);
+                    current.clear();
+                }
+            },
+            character => current.push(character),
+        }
+    }
+
+    attributes
+}
+
+fn parse_tag(content: &str) -> Option<Token> {
+    let raw = format!("{}{}{}", TAG_START, content, TAG_END);
+
+    if let Some(name) = content.strip_prefix(CLOSING) {
+        let name = name.trim();
+
+        if name.is_empty() {
+            return None;
+        }
+
+        return Some(Token::Close { name: name.to_string(), raw });
+    }
+
+    let content = content.trim();
+    let (name, attributes) = match content.find(char::is_whitespace) {
+        Some(position) => content.split_at(position),
+        None => (content, ""),
+    };
+
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(Token::Open {
+        name: name.to_string(),
+        attrs: parse_attributes(attributes),
+        raw,
+    })
+}
+
+/// Character of DSL line, either as is or escaped with backslash.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Character {
+    Plain(char),
+    Escaped(char),
+}
+
+impl Character {
+    // Character as it was written in DSL.
+    pub fn push_dsl(self, dsl: &mut String) {
+        match self {
+            Character::Plain(character) => dsl.push(character),
+            Character::Escaped(character) => {
+                dsl.push(ESCAPE);
+                dsl.push(character);
+            },
+        }
+    }
+}
+
+/*
+ * Characters of DSL line, with backslash escaping any following character.
+ * Lone backslash at the end is literal. Every other reader of DSL text builds on this,
+ * so that escaped characters are never mistaken for markup, comments or headword parts.
+ */
+pub fn characters(line: &str) -> impl Iterator<Item = Character> + '_ {
+    let mut characters = line.chars();
+
+    std::iter::from_fn(move || {
+        let character = characters.next()?;
+
+        if character != ESCAPE {
+            return Some(Character::Plain(character));
+        }
+
+        match characters.next() {
+            Some(escaped) => Some(Character::Escaped(escaped)),
+            None => Some(Character::Plain(character)),
+        }
+    })
+}
+
+fn to_dsl(characters: &[Character]) -> String {
+    let mut dsl = String::with_capacity(characters.len());
+
+    for character in characters {
+        character.push_dsl(&mut dsl);
+    }
+
+    dsl
+}
+
+// Splits DSL line to text, escaped characters and tags.
+pub fn tokenize(line: &str) -> Vec<Token> {
+    let characters: Vec<Character> = characters(line).collect();
+    let mut tokens = vec![];
+    let mut position = 0;
+
+    while position < characters.len() {
+        match characters[position] {
+            Character::Escaped(character) => tokens.push(Token::Escaped(character)),
+            Character::Plain(TAG_START) => {
+                let rest = &characters[position + 1..];
+                let tag = rest
+                    .iter()
+                    .position(|character| *character == Character::Plain(TAG_END))
+                    .filter(|end| !rest[..*end].contains(&Character::Plain(TAG_START)))
+                    .and_then(|end| parse_tag(&to_dsl(&rest[..end])).map(|token| (end, token)));
+
+                match tag {
+                    Some((end, token)) => {
+                        tokens.push(token);
+                        // Skip tag content and closing bracket.
+                        position += end + 2;
+                        continue;
+                    },
+                    None => push_text_token(&mut tokens, TAG_START),
+                }
+            },
+            Character::Plain(character) => push_text_token(&mut tokens, character),
+        }
+
+        position += 1;
+    }
+
+    tokens
+}
+
+// Closing tag matches opening tag of same name. Margins are special: [/m] closes [m1]...[m9].
+fn closes(open_name: &str, close_name: &str) -> bool {
+    if open_name == close_name {
+        return true;
+    }
+
+    match open_name.strip_prefix(close_name) {
+        Some(level) => close_name == MARGIN && !level.is_empty() && level.chars().all(|character| character.is_ascii_digit()),
+        None => false,
+    }
+}
+
+// Margins are closed with [/m], regardless of level.
+fn closing_name(name: &str) -> &str {
+    if closes(name, MARGIN) { MARGIN } else { name }
+}
+
+// Closing tag of the given tag as written in DSL, eg. [/m] for [m1].
+pub fn close_tag(name: &str) -> String {
+    format!("{}{}{}{}", TAG_START, CLOSING, closing_name(name), TAG_END)
+}
+
+fn close_open_tag(stack: &mut Vec<OpenTag>, root: &mut Vec<Markup>) {
+    if let Some(tag) = stack.pop() {
+        // Reopened tag that got no content is left out: [b][i]foo[/b][/i]
+        if tag.reopened && tag.children.is_empty() {
+            return;
+        }
+
+        let node = Markup::Tag { name: tag.name, attrs: tag.attrs, raw: tag.raw, children: tag.children };
+
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => root.push(node),
+        }
+    }
+}
+
+fn current_children<'a>(stack: &'a mut [OpenTag], root: &'a mut Vec<Markup>) -> &'a mut Vec<Markup> {
+    match stack.last_mut() {
+        Some(tag) => &mut tag.children,
+        None => root,
+    }
+}
+
+/*
+ * Builds tree of tokens. Overlapping tags are split into properly nested ones,
+ * and tags left open are closed.
+ */
+pub fn parse_tokens(tokens: Vec<Token>) -> Vec<Markup> {
+    let mut root: Vec<Markup> = vec![];
+    let mut stack: Vec<OpenTag> = vec![];
+
+    for token in tokens {
+        match token {
+            Token::Text(text) => push_text(current_children(&mut stack, &mut root), &text),
+            Token::Escaped(character) => current_children(&mut stack, &mut root).push(Markup::Escaped(character)),
+            Token::Open { name, attrs, raw } => stack.push(OpenTag { name, attrs, raw, children: vec![], reopened: false }),
+            Token::Close { name, raw } => {
+                let position = match stack.iter().rposition(|tag| closes(&tag.name, &name)) {
+                    Some(position) => position,
+                    None => {
+                        current_children(&mut stack, &mut root).push(Markup::Close { name, raw });
+                        continue;
+                    },
+                };
+
+                // Tags opened inside the closed one are closed too, and reopened after it.
+                let mut reopened = vec![];
+                while stack.len() > position + 1 {
+                    let tag = &stack[stack.len() - 1];
+                    reopened.push((tag.name.clone(), tag.attrs.clone(), tag.raw.clone()));
+                    close_open_tag(&mut stack, &mut root);
+                }
+
+                close_open_tag(&mut stack, &mut root);
+
+                for (name, attrs, raw) in reopened.into_iter().rev() {
+                    stack.push(OpenTag { name, attrs, raw, children: vec![], reopened: true });
+                }
+            },
+        }
+    }
+
+    while !stack.is_empty() {
+        close_open_tag(&mut stack, &mut root);
+    }
+
+    root
+}
+
+// Public nodes of markup, where escaped characters are plain text.
+pub fn to_nodes(markup: &[Markup]) -> Vec<Node> {
+    let mut nodes: Vec<Node> = vec![];
+
+    for node in markup {
+        let text = match node {
+            Markup::Text(text) => text.clone(),
+            Markup::Escaped(character) => character.to_string(),
+            Markup::Close { raw, .. } => raw.clone(),
+            Markup::Tag { name, attrs, children, .. } => {
+                nodes.push(Node::Tag { name: name.clone(), attrs: attrs.clone(), children: to_nodes(children) });
+                continue;
+            },
+        };
+
+        match nodes.last_mut() {
+            Some(Node::Text(previous)) => previous.push_str(&text),
+            _ => nodes.push(Node::Text(text)),
+        }
+    }
+
+    nodes
+}
+
+// Markup of public nodes. Text of nodes is all plain text.
+fn from_nodes(nodes: &[Node]) -> Vec<Markup> {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Text(text) => Markup::Text(text.clone()),
+            Node::Tag { name, attrs, children } => Markup::Tag {
+                name: name.clone(),
+                attrs: attrs.clone(),
+                raw: format!("{}{}{}{}", TAG_START, name, write_attributes(attrs), TAG_END),
+                children: from_nodes(children),
+            },
+        })
+        .collect()
+}
+
+/// Parse a line of DSL markup into a tree of nodes.
+///
+/// Handles nesting, tag attributes and backslash escapes. DSL allows overlapping
+/// tags like `[b][i]foo[/b]bar[/i]`; these are split into properly nested tags.
+/// Closing tags without matching opening tag are kept as text,
+/// and tags left open at the end of line are closed.
+///
+/// # Examples
+///
+/// ```
+/// use harlaw::{parse_markup, Attribute, Node};
+///
+/// let nodes = parse_markup("[lang id=1031]Haus[/lang] \\[sic\\]");
+///
+/// assert_eq!(nodes, vec![
+///     Node::Tag {
+///         name: String::from("lang"),
+///         attrs: vec![Attribute { name: String::from("id"), value: Some(String::from("1031")) }],
+///         children: vec![Node::Text(String::from("Haus"))],
+///     },
+///     Node::Text(String::from(" [sic]")),
+/// ]);
+/// ```
+///
+pub fn parse_markup(line: &str) -> Vec<Node> {
+    to_nodes(&parse_tokens(tokenize(line)))
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars() {
+        if SPECIAL_CHARACTERS.contains(&character) {
+            escaped.push(ESCAPE);
+        }
+        escaped.push(character);
+    }
+
+    escaped
+}
+
+fn write_attributes(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .map(|attribute| match &attribute.value {
+            Some(value) if value.contains(char::is_whitespace) => format!(" {}=\"{}\"", attribute.name, value),
+            Some(value) => format!(" {}={}", attribute.name, value),
+            None => format!(" {}", attribute.name),
+        })
+        .collect()
+}
+
+/// Serialize nodes back to DSL markup. Characters with meaning in DSL, like brackets, `~` and `{`, are escaped in text.
+///
+/// # Examples
+///
+/// ```
+/// use harlaw::{parse_markup, markup_to_dsl};
+///
+/// let line = "[m1][c red]foo[/c] \\[bar\\][/m]";
+///
+/// assert_eq!(markup_to_dsl(&parse_markup(line)), line);
+/// ```
+///
+pub fn markup_to_dsl(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Text(text) => escape_text(text),
+            Node::Tag { name, attrs, children } => format!(
+                "{}{}{}{}{}{}",
+                TAG_START,
+                name,
+                write_attributes(attrs),
+                TAG_END,
+                markup_to_dsl(children),
+                close_tag(name)
+            ),
+        })
+        .collect()
+}
+
+/// Render nodes as plain text, without any markup.
+///
+/// # Examples
+///
+/// ```
+/// use harlaw::{parse_markup, markup_to_text};
+///
+/// assert_eq!(markup_to_text(&parse_markup("[m1][b]foo[/b] bar[/m]")), "foo bar");
+/// ```
+///
+pub fn markup_to_text(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Text(text) => text.clone(),
+            Node::Tag { children, .. } => markup_to_text(children),
+        })
+        .collect()
+}
+
+// Whitespace of labels and references is collapsed to single spaces: "v.  refl. " -> "v. refl."
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Text of the link in <<word>> shorthand for references.
+fn shorthand_references(text: &str) -> Vec<String> {
+    let mut references = vec![];
+    let mut rest = text;
+
+    while let Some(start) = rest.find(REFERENCE_START) {
+        let after_start = &rest[start + REFERENCE_START.len()..];
+
+        match after_start.find(REFERENCE_END) {
+            Some(end) => {
+                references.push(after_start[..end].to_string());
+                rest = &after_start[end + REFERENCE_END.len()..];
+            },
+            None => break,
+        }
+    }
+
+    references
+}
+
+/*
+ * Collects referenced headwords, both [ref]word[/ref] tags and <<word>> shorthands.
+ * Whitespace of references is normalised, and empty ones are left out.
+ */
+pub fn markup_references(nodes: &[Node]) -> Vec<String> {
+    let references = nodes.iter().flat_map(|node| match node {
+        Node::Text(text) => shorthand_references(text),
+        Node::Tag { name, children, .. } if name == REFERENCE => vec![markup_to_text(children)],
+        Node::Tag { children, .. } => markup_references(children),
+    });
+
+    references
+        .map(|reference| collapse_whitespace(&reference))
+        .filter(|reference| !reference.is_empty())
+        .collect()
+}
+
+// File names of [s] media tags.
+pub fn markup_media(nodes: &[Node]) -> Vec<String> {
+    nodes
+        .iter()
+        .flat_map(|node| match node {
+            Node::Text(_) => vec![],
+            Node::Tag { name, children, .. } if name == MEDIA => vec![markup_to_text(children).trim().to_string()],
+            Node::Tag { children, .. } => markup_media(children),
+        })
+        .filter(|file| !file.is_empty())
+        .collect()
+}
+
+// Texts of [p] labels, with whitespace normalised.
+pub fn markup_labels(nodes: &[Node]) -> Vec<String> {
+    let labels = nodes.iter().flat_map(|node| match node {
+        Node::Text(_) => vec![],
+        Node::Tag { name, children, .. } if name == LABEL => vec![markup_to_text(children)],
+        Node::Tag { children, .. } => markup_labels(children),
+    });
+
+    labels
+        .map(|label| collapse_whitespace(&label))
+        .filter(|label| !label.is_empty())
+        .collect()
+}
+
+/// Render nodes as HTML. Tags are transformed like with default settings,
+/// and text is escaped for HTML. Tags without HTML equilevant are left out, keeping their content.
+///
+/// # Examples
+///
+/// ```
+/// use harlaw::{parse_markup, markup_to_html};
+///
+/// let nodes = parse_markup("[m1][b]foo[/b] & [ref]bar[/ref][/m]");
+///
+/// assert_eq!(markup_to_html(&nodes), "<strong>foo</strong> &amp; <span class=\"reference\">bar</span>");
+/// ```
+///
+pub fn markup_to_html(nodes: &[Node]) -> String {
+    formatter::markup_to_html(&from_nodes(nodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(content: &str) -> Node {
+        Node::Text(String::from(content))
+    }
+
+    fn tag(name: &str, children: Vec<Node>) -> Node {
+        Node::Tag { name: String::from(name), attrs: vec![], children }
+    }
+
+    #[test]
+    fn parses_nested_tags() {
+        let result = parse_markup("[m1]Lorem [b]ipsum [i]dolor[/i][/b] sit[/m]");
+
+        assert_eq!(result, vec![
+            tag("m1", vec![
+                text("Lorem "),
+                tag("b", vec![text("ipsum "), tag("i", vec![text("dolor")])]),
+                text(" sit"),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let result = parse_markup("[c red]foo[/c][lang name=\"Old Norse\" id=1]bar[/lang]");
+
+        assert_eq!(result, vec![
+            Node::Tag {
+                name: String::from("c"),
+                attrs: vec![Attribute { name: String::from("red"), value: None }],
+                children: vec![text("foo")],
+            },
+            Node::Tag {
+                name: String::from("lang"),
+                attrs: vec![
+                    Attribute { name: String::from("name"), value: Some(String::from("Old Norse")) },
+                    Attribute { name: String::from("id"), value: Some(String::from("1")) },
+                ],
+                children: vec![text("bar")],
+            },
+        ]);
+    }
+
+    #[test]
+    fn splits_overlapping_tags() {
+        let result = parse_markup("[b][i]foo[/b]bar[/i]");
+
+        assert_eq!(result, vec![
+            tag("b", vec![tag("i", vec![text("foo")])]),
+            tag("i", vec![text("bar")]),
+        ]);
+        assert_eq!(parse_markup("[b][i]foo[/b][/i]"), vec![tag("b", vec![tag("i", vec![text("foo")])])]);
+    }
+
+    #[test]
+    fn keeps_escaped_brackets_as_text() {
+        let result = parse_markup("\\[b\\]foo\\\\ [i]bar\\][/i]");
+
+        assert_eq!(result, vec![
+            text("[b]foo\\ "),
+            tag("i", vec![text("bar]")]),
+        ]);
+    }
+
+    #[test]
+    fn keeps_unmatched_and_unknown_markup() {
+        let result = parse_markup("foo[/b] [unknown]bar[/unknown] [baz");
+
+        assert_eq!(result, vec![
+            text("foo[/b] "),
+            tag("unknown", vec![text("bar")]),
+            text(" [baz"),
+        ]);
+    }
+
+    #[test]
+    fn closes_open_tags_at_end_of_line() {
+        let result = parse_markup("[m1][b]foo");
+
+        assert_eq!(result, vec![tag("m1", vec![tag("b", vec![text("foo")])])]);
+    }
+
+    #[test]
+    fn serializes_back_to_dsl() {
+        let line = "[m2][b]foo[/b] [lang name=\"Old Norse\"]bar[/lang] \\[baz\\][/m]";
+
+        assert_eq!(markup_to_dsl(&parse_markup(line)), line);
+    }
+
+    #[test]
+    fn escapes_special_characters_in_round_trip() {
+        let line = "[m1]\\~ \\{\\{not a comment\\}\\} \\@ \\# \\^ [b]C:\\\\[/b][/m]";
+        let nodes = parse_markup(line);
+
+        assert_eq!(nodes, vec![tag("m1", vec![text("~ {{not a comment}} @ # ^ "), tag("b", vec![text("C:\\")])])]);
+        assert_eq!(markup_to_dsl(&nodes), line);
+        assert_eq!(parse_markup(&markup_to_dsl(&nodes)), nodes);
+    }
+
+    #[test]
+    fn renders_html_like_default_settings() {
+        let nodes = parse_markup("[m1][p]n.[/p] [i]constipation[/i], [sup]1[/sup] [com]see[/com] [ref]aðal[/ref].[/m]");
+
+        assert_eq!(
+            markup_to_html(&nodes),
+            "<span>n.</span> <i>constipation</i>, <sup>1</sup> see <span class=\"reference\">aðal</span>."
+        );
+    }
+
+    #[test]
+    fn renders_plain_text() {
+        let nodes = parse_markup("[m1][p]n.[/p] [i]constipation[/i] \\[1\\][/m]");
+
+        assert_eq!(markup_to_text(&nodes), "n. constipation [1]");
+    }
+
+    #[test]
+    fn collects_references() {
+        let nodes = parse_markup("[m1]see [ref]aðal[/ref], [i][ref dict=\"x\"]foo  bar[/ref][/i] and <<baz>>, [ref][/ref][/m]");
+
+        assert_eq!(markup_references(&nodes), vec!["aðal", "foo bar", "baz"]);
+    }
+
+    #[test]
+    fn collects_labels() {
+        let nodes = parse_markup("[m1][p]f.[/p] [i][p]v.  refl.[/p][/i] [p][/p][/m]");
+
+        assert_eq!(markup_labels(&nodes), vec!["f.", "v. refl."]);
+    }
+
+    #[test]
+    fn renders_language_tags_as_html() {
+        let nodes = parse_markup("[lang id=1031]Haus[/lang] [lang name=\"Icelandic\"]hús[/lang] [lang id=1]x[/lang]");
+
+        assert_eq!(markup_to_html(&nodes), "<span lang=\"de\">Haus</span> <span lang=\"is\">hús</span> x");
+    }
+
+    #[test]
+    fn collects_media() {
+        let nodes = parse_markup("[m1][s]hus.wav[/s] [i][s] hus.bmp [/s][/i] [s][/s][/m]");
+
+        assert_eq!(markup_media(&nodes), vec!["hus.wav", "hus.bmp"]);
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use crate::{DictionaryEntry, HarlawError, MediaKind, MediaLocation};
+use crate::dictionary::dictionary_stem;
+use crate::settings::MediaUrls;
+
+// Lingvo keeps media files of foo.dsl in foo.dsl.files.zip, or unpacked in foo.dsl.files directory.
+const RESOURCES_SUFFIX: &str = ".files";
+const ARCHIVE_EXTENSION: &str = ".zip";
+const DSL_EXTENSION: &str = ".dsl";
+// Lingvo refers to files in subdirectories with backslash, while archives use slash.
+const PATH_SEPARATOR: &str = "/";
+
+const SOUNDS: &[&str] = &["wav", "mp3", "ogg", "oga", "opus", "flac", "m4a", "spx", "aac", "wma"];
+const IMAGES: &[&str] = &["bmp", "jpg", "jpeg", "png", "gif", "tif", "tiff", "svg", "webp", "ico"];
+const VIDEOS: &[&str] = &["mp4", "avi", "webm", "mkv", "mov", "wmv", "mpg", "mpeg"];
+
+pub fn media_kind(file: &str) -> MediaKind {
+    let extension = Path::new(file)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    if SOUNDS.contains(&extension.as_str()) {
+        MediaKind::Sound
+    } else if IMAGES.contains(&extension.as_str()) {
+        MediaKind::Image
+    } else if VIDEOS.contains(&extension.as_str()) {
+        MediaKind::Video
+    } else {
+        MediaKind::Other
+    }
+}
+
+fn escape_url(url: &str) -> String {
+    url.replace('&', "&amp;")
+        .replace('"', "%22")
+        .replace('<', "%3C")
+        .replace('>', "%3E")
+        .replace(' ', "%20")
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// HTML element for media file: audio, image, video or plain link.
+pub fn media_html(file: &str, urls: &MediaUrls) -> String {
+    match media_kind(file) {
+        MediaKind::Sound => format!("<audio controls src=\"{}\"></audio>", escape_url(&format!("{}{}", urls.sound, file))),
+        MediaKind::Image => format!("<img src=\"{}\" alt=\"\">", escape_url(&format!("{}{}", urls.image, file))),
+        MediaKind::Video => format!("<video controls src=\"{}\"></video>", escape_url(&format!("{}{}", urls.video, file))),
+        MediaKind::Other => format!("<a href=\"{}\">{}</a>", escape_url(&format!("{}{}", urls.other, file)), escape_text(file)),
+    }
+}
+
+/*
+ * Files available for the dictionary, keyed by lowercase name,
+ * as Lingvo comes from a case-insensitive file system.
+ */
+enum Resources {
+    Directory(PathBuf, HashMap<String, String>),
+    Archive(PathBuf, HashMap<String, String>),
+}
+
+fn by_lowercase(names: impl Iterator<Item = String>) -> HashMap<String, String> {
+    names.map(|name| (name.to_lowercase(), name)).collect()
+}
+
+// Files of the directory and its subdirectories, by path relative to it, like in archives: images/hus.png
+fn read_directory(directory: &Path) -> Result<HashMap<String, String>, HarlawError> {
+    let mut names = vec![];
+    let mut directories = vec![(directory.to_path_buf(), String::new())];
+
+    while let Some((directory, prefix)) = directories.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                directories.push((entry.path(), format!("{}{}", name, PATH_SEPARATOR)));
+            } else if file_type.is_file() {
+                names.push(name);
+            }
+        }
+    }
+
+    Ok(by_lowercase(names.into_iter()))
+}
+
+fn read_archive(archive: &Path) -> Result<HashMap<String, String>, HarlawError> {
+    let archive = zip::ZipArchive::new(File::open(archive)?)
+        .map_err(|error| HarlawError::Io(error.into()))?;
+
+    Ok(by_lowercase(archive.file_names().map(|name| name.to_string())))
+}
+
+// Both foo.dsl.dz.files.zip and foo.dsl.files.zip are used for compressed dictionaries, .dsl.gz alike.
+fn find_resources(filepath: &str) -> Result<Option<Resources>, HarlawError> {
+    let mut bases = vec![filepath.to_string()];
+
+    if let Some(uncompressed) = dictionary_stem(filepath).map(|stem| format!("{}{}", stem, DSL_EXTENSION)) {
+        if uncompressed != filepath {
+            bases.push(uncompressed);
+        }
+    }
+
+    for base in bases {
+        let directory = PathBuf::from(format!("{}{}", base, RESOURCES_SUFFIX));
+        let archive = PathBuf::from(format!("{}{}{}", base, RESOURCES_SUFFIX, ARCHIVE_EXTENSION));
+
+        if archive.is_file() {
+            let names = read_archive(&archive)?;
+            return Ok(Some(Resources::Archive(archive, names)));
+        }
+
+        if directory.is_dir() {
+            let names = read_directory(&directory)?;
+            return Ok(Some(Resources::Directory(directory, names)));
+        }
+    }
+
+    Ok(None)
+}
+
+fn locate(resources: &Resources, file: &str) -> Option<MediaLocation> {
+    let key = file.replace('\\', PATH_SEPARATOR).to_lowercase();
+
+    match resources {
+        Resources::Directory(directory, names) => names.get(&key).map(|name| {
+            MediaLocation::File(directory.join(name).to_string_lossy().to_string())
+        }),
+        Resources::Archive(archive, names) => names.get(&key).map(|name| MediaLocation::Archive {
+            archive: archive.to_string_lossy().to_string(),
+            file: name.clone(),
+        }),
+    }
+}
+
+pub fn resolve_media(filepath: &str, entries: &mut [DictionaryEntry]) -> Result<(), HarlawError> {
+    let resources = match find_resources(filepath)? {
+        Some(resources) => resources,
+        None => return Ok(()),
+    };
+
+    locate_entries(&resources, entries);
+    Ok(())
+}
+
+// Subentries carry their own media, so they are resolved as well.
+fn locate_entries(resources: &Resources, entries: &mut [DictionaryEntry]) {
+    for entry in entries {
+        for media in &mut entry.media {
+            media.location = locate(resources, &media.file);
+        }
+        locate_entries(resources, &mut entry.subentries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MediaRef;
+
+    fn entry_with_media(files: &[&str]) -> DictionaryEntry {
+        DictionaryEntry {
+            word: String::from("foo"),
+            media: files.iter().map(|file| MediaRef {
+                file: file.to_string(),
+                kind: media_kind(file),
+                location: None,
+            }).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn detects_media_kind_from_extension() {
+        assert_eq!(media_kind("hus.WAV"), MediaKind::Sound);
+        assert_eq!(media_kind("hus.bmp"), MediaKind::Image);
+        assert_eq!(media_kind("hus.mp4"), MediaKind::Video);
+        assert_eq!(media_kind("hus"), MediaKind::Other);
+    }
+
+    #[test]
+    fn renders_media_as_html() {
+        let urls = MediaUrls {
+            sound: "https://example.com/sounds/",
+            ..Default::default()
+        };
+
+        assert_eq!(media_html("hús 1.wav", &urls), "<audio controls src=\"https://example.com/sounds/hús%201.wav\"></audio>");
+        assert_eq!(media_html("hus.png", &urls), "<img src=\"hus.png\" alt=\"\">");
+        assert_eq!(media_html("a&b.txt", &urls), "<a href=\"a&amp;b.txt\">a&amp;b.txt</a>");
+    }
+
+    #[test]
+    fn resolves_media_from_resource_directory() {
+        let mut entries = vec![entry_with_media(&["hus.wav", "images\\hus.png", "missing.wav"])];
+
+        resolve_media("./src/media/fixtures/directory/dictionary.dsl", &mut entries).unwrap();
+
+        let directory = Path::new("./src/media/fixtures/directory/dictionary.dsl.files");
+        assert_eq!(entries[0].media[0].location, Some(MediaLocation::File(directory.join("HUS.wav").to_string_lossy().to_string())));
+        assert_eq!(entries[0].media[1].location, Some(MediaLocation::File(directory.join("Images/hus.png").to_string_lossy().to_string())));
+        assert_eq!(entries[0].media[2].location, None);
+    }
+
+    #[test]
+    fn resolves_media_from_resource_archive() {
+        let mut entries = vec![entry_with_media(&["hus.wav", "images\\hus.png"])];
+
+        resolve_media("./src/media/fixtures/archive/dictionary.dsl.dz", &mut entries).unwrap();
+
+        assert_eq!(entries[0].media[0].location, Some(MediaLocation::Archive {
+            archive: String::from("./src/media/fixtures/archive/dictionary.dsl.files.zip"),
+            file: String::from("hus.wav"),
+        }));
+        assert_eq!(entries[0].media[1].location, Some(MediaLocation::Archive {
+            archive: String::from("./src/media/fixtures/archive/dictionary.dsl.files.zip"),
+            file: String::from("images/hus.png"),
+        }));
+    }
+
+    #[test]
+    fn resolves_media_of_gzipped_dictionary() {
+        let mut entries = vec![entry_with_media(&["hus.wav"])];
+
+        resolve_media("./src/media/fixtures/directory/dictionary.dsl.gz", &mut entries).unwrap();
+
+        let directory = Path::new("./src/media/fixtures/directory/dictionary.dsl.files");
+        assert_eq!(entries[0].media[0].location, Some(MediaLocation::File(directory.join("HUS.wav").to_string_lossy().to_string())));
+    }
+
+    #[test]
+    fn resolves_media_of_subentries() {
+        let mut entries = vec![entry_with_media(&[])];
+        entries[0].subentries.push(entry_with_media(&["hus.wav"]));
+
+        resolve_media("./src/media/fixtures/directory/dictionary.dsl", &mut entries).unwrap();
+
+        let directory = Path::new("./src/media/fixtures/directory/dictionary.dsl.files");
+        assert_eq!(entries[0].subentries[0].media[0].location, Some(MediaLocation::File(directory.join("HUS.wav").to_string_lossy().to_string())));
+    }
+
+    #[test]
+    fn leaves_media_unresolved_without_resources() {
+        let mut entries = vec![entry_with_media(&["hus.wav"])];
+
+        resolve_media("./src/media/fixtures/none.dsl", &mut entries).unwrap();
+
+        assert_eq!(entries[0].media[0].location, None);
+    }
+}
@@ -1,33 +1,313 @@
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Cursor, Read};
 use std::path::Path;
+use std::char::REPLACEMENT_CHARACTER;
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::MultiGzDecoder;
+use crate::HarlawError;
 
-fn is_dsl_file(filename: &str) -> bool {
-    Path::new(&filename).extension().unwrap().eq("dsl")
+const SOURCE_CODE_PAGE: &str = "#SOURCE_CODE_PAGE";
+const HEADER_START: char = '#';
+const SNIFF_LENGTH: u64 = 64 * 1024;
+const UTF8_BOM: char = '\u{FEFF}';
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+
+// Code page names used by Lingvo in #SOURCE_CODE_PAGE headers.
+const CODE_PAGES: &[(&str, &str)] = &[
+    ("Latin", "windows-1252"),
+    ("Cyrillic", "windows-1251"),
+    ("EasternEuropean", "windows-1250"),
+    ("Greek", "windows-1253"),
+    ("Turkish", "windows-1254"),
+    ("Hebrew", "windows-1255"),
+    ("Arabic", "windows-1256"),
+    ("Baltic", "windows-1257"),
+    ("Vietnamese", "windows-1258"),
+    ("Thai", "windows-874"),
+];
+
+// Plain .dsl files, and compressed .dsl.dz (dictzip) or .dsl.gz files.
+pub fn is_dsl_file(filename: &str) -> bool {
+    let path = Path::new(&filename);
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("dsl") => true,
+        Some("dz") | Some("gz") => path
+            .file_stem()
+            .and_then(|stem| Path::new(stem).extension())
+            .is_some_and(|extension| extension.eq("dsl")),
+        _ => false,
+    }
+}
+
+fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.starts_with(GZIP_MAGIC)
+}
+
+/*
+ * Dictzip files are valid gzip files, with a chunk table in the header
+ * for random access. Whole dictionary is read anyway, so they can be
+ * decompressed as regular gzip.
+ */
+fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>, HarlawError> {
+    if !is_compressed(&bytes) {
+        return Ok(bytes);
+    }
+
+    let mut decompressed = vec![];
+    MultiGzDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed)?;
+
+    Ok(decompressed)
+}
+
+/// Decoded DSL lines. Undecodable characters are replaced with U+FFFD,
+/// and the numbers of lines containing them are listed in `broken_lines`.
+pub struct DecodedLines {
+    pub lines: Vec<String>,
+    pub broken_lines: Vec<usize>,
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> DecodedLines {
+    let pairs = bytes.chunks_exact(2);
+    let is_truncated = !pairs.remainder().is_empty();
+    let units = pairs.map(|pair| to_unit([pair[0], pair[1]]));
+    let mut content = String::new();
+    let mut line = 1;
+    let mut broken_lines = vec![];
+
+    for decoded in char::decode_utf16(units) {
+        if decoded.is_err() && broken_lines.last() != Some(&line) {
+            broken_lines.push(line);
+        }
+
+        let character = decoded.unwrap_or(REPLACEMENT_CHARACTER);
+
+        if character == '\n' {
+            line += 1;
+        }
+
+        content.push(character);
+    }
+
+    if is_truncated && broken_lines.last() != Some(&line) {
+        broken_lines.push(line);
+    }
+
+    DecodedLines {
+        lines: content.lines().map(String::from).collect(),
+        broken_lines,
+    }
+}
+
+// ASCII compatible encodings are decoded line by line, to know which line is broken.
+fn decode_lines(bytes: &[u8], encoding: &'static Encoding) -> DecodedLines {
+    if encoding == UTF_16LE {
+        return decode_utf16(bytes, u16::from_le_bytes);
+    }
+
+    if encoding == UTF_16BE {
+        return decode_utf16(bytes, u16::from_be_bytes);
+    }
+
+    let mut lines = vec![];
+    let mut broken_lines = vec![];
+
+    for (index, raw_line) in bytes.split(|byte| *byte == b'\n').enumerate() {
+        let raw_line = raw_line.strip_suffix(b"\r").unwrap_or(raw_line);
+        let (line, had_errors) = encoding.decode_without_bom_handling(raw_line);
+
+        if had_errors {
+            broken_lines.push(index + 1);
+        }
+
+        lines.push(line.into_owned());
+    }
+
+    // Trailing newline does not start a new line.
+    if bytes.ends_with(b"\n") {
+        lines.pop();
+    }
+
+    DecodedLines { lines, broken_lines }
+}
+
+// Accepts both Lingvo code page names and standard encoding labels, eg. "Cyrillic" or "windows-1251".
+fn find_encoding(label: &str) -> Option<&'static Encoding> {
+    let label = label.trim().trim_matches('"');
+
+    let code_page = CODE_PAGES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(label))
+        .map(|(_, encoding)| *encoding)
+        .unwrap_or(label);
+
+    Encoding::for_label(code_page.as_bytes())
+}
+
+// Headers are plain ASCII in every code page, so they can be inspected before decoding.
+fn find_source_code_page(bytes: &[u8]) -> Option<&'static Encoding> {
+    for raw_line in bytes.split(|byte| *byte == b'\n') {
+        let line = String::from_utf8_lossy(raw_line);
+
+        if !line.starts_with('#') {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix(SOURCE_CODE_PAGE) {
+            return find_encoding(value);
+        }
+    }
+
+    None
+}
+
+// Truncated multi-byte character at the end of sniffed prefix does not make it invalid.
+fn is_valid_utf8_prefix(bytes: &[u8]) -> bool {
+    match std::str::from_utf8(bytes) {
+        Ok(_content) => true,
+        Err(error) => error.error_len().is_none(),
+    }
+}
+
+/*
+ * Lingvo saves DSL files as UTF-16LE by default, but other tools
+ * commonly produce UTF-16BE or UTF-8. Sniff the byte order mark
+ * to pick the decoding, falling back to UTF-8 when there is none.
+ *
+ * Older dictionaries use legacy single byte encodings, declared
+ * in #SOURCE_CODE_PAGE header. Many of them have since been converted
+ * to UTF-8 without updating the header, so the header is only
+ * honored when the content is not valid UTF-8.
+ */
+fn detect_encoding(bytes: &[u8], encoding: Option<&str>) -> Result<&'static Encoding, HarlawError> {
+    if let Some(label) = encoding {
+        return find_encoding(label)
+            .ok_or_else(|| HarlawError::UnsupportedEncoding(label.to_string()));
+    }
+
+    if let Some((encoding, _bom_length)) = Encoding::for_bom(bytes) {
+        return Ok(encoding);
+    }
+
+    if !is_valid_utf8_prefix(bytes) {
+        if let Some(encoding) = find_source_code_page(bytes) {
+            return Ok(encoding);
+        }
+    }
+
+    Ok(UTF_8)
 }
 
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where P: AsRef<Path>, {
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+fn decode_dsl_bytes(bytes: &[u8], encoding: Option<&str>) -> Result<DecodedLines, HarlawError> {
+    let encoding = detect_encoding(bytes, encoding)?;
+    let content = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, bom_length)) if bom_encoding == encoding => &bytes[bom_length..],
+        _ => bytes,
+    };
+
+    Ok(decode_lines(content, encoding))
 }
 
-pub fn read_dsl_file(filename: &str) -> Result<Vec<String>, &'static str> {
-    if is_dsl_file(filename) {
-        return match read_lines(&filename) {
-            Ok(line_results) => {
-                let mut lines = vec![];
-                for line_result in line_results {
-                   lines.push(line_result.unwrap_or_else(|_| String::from("Could not read all DSL lines")));
-                }
+/*
+ * Decodes content of DSL file, or its companion text file like .ann annotation,
+ * replacing undecodable characters instead of failing. Compressed content is decompressed first.
+ */
+pub fn decode_bytes_lossy(bytes: Vec<u8>, encoding: Option<&str>) -> Result<DecodedLines, HarlawError> {
+    let bytes = decompress(bytes)?;
 
-                Ok(lines)
-            }, 
-            Err(_e) => Err("Could not read the given DSL file"),
+    decode_dsl_bytes(&bytes, encoding)
+}
+
+// Reads DSL file, replacing undecodable characters instead of failing.
+pub fn read_dsl_file_lossy(filename: &str, encoding: Option<&str>) -> Result<DecodedLines, HarlawError> {
+    if !is_dsl_file(filename) {
+        return Err(HarlawError::NotDsl);
+    }
+
+    decode_bytes_lossy(fs::read(filename)?, encoding)
+}
+
+/*
+ * Opens DSL file for streaming, transcoding it to UTF-8 on the fly.
+ * Encoding is detected from the beginning of the file, which contains
+ * the byte order mark and headers. Invalid UTF-8 is passed through as is,
+ * so that the line can be reported as broken.
+ */
+pub fn open_dsl_stream(filename: &str, encoding: Option<&str>) -> Result<Box<dyn BufRead>, HarlawError> {
+    if !is_dsl_file(filename) {
+        return Err(HarlawError::NotDsl);
+    }
+
+    let mut file = BufReader::new(File::open(filename)?);
+    let mut source: Box<dyn Read> = if is_compressed(file.fill_buf()?) {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+
+    let mut prefix = vec![];
+    Read::by_ref(&mut source).take(SNIFF_LENGTH).read_to_end(&mut prefix)?;
+    let encoding = detect_encoding(&prefix, encoding)?;
+
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .utf8_passthru(true)
+        .strip_bom(true)
+        .build(Cursor::new(prefix).chain(source));
+
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+/*
+ * Reads only the headers from the beginning of DSL file, stopping at the first entry line.
+ * Headers are plain text, so characters that can not be decoded are replaced rather than failing.
+ */
+pub fn read_dsl_headers(filename: &str, encoding: Option<&str>) -> Result<Vec<String>, HarlawError> {
+    let mut stream = open_dsl_stream(filename, encoding)?;
+    let mut headers = vec![];
+    let mut buffer = vec![];
+
+    while stream.read_until(b'\n', &mut buffer)? > 0 {
+        let line = String::from_utf8_lossy(&buffer).trim_end_matches(['\n', '\r']).to_string();
+        buffer.clear();
+
+        if !line.trim().is_empty() && !line.trim_start().starts_with(HEADER_START) {
+            break;
         }
+
+        headers.push(line);
     }
 
-    Err("Given file was not a DSL file")
+    Ok(headers)
+}
+
+fn into_lines(decoded: DecodedLines) -> Result<Vec<String>, HarlawError> {
+    match decoded.broken_lines.first() {
+        Some(line) => Err(HarlawError::Encoding { line: *line }),
+        None => Ok(decoded.lines),
+    }
+}
+
+pub fn read_dsl_file(filename: &str, encoding: Option<&str>) -> Result<Vec<String>, HarlawError> {
+    into_lines(read_dsl_file_lossy(filename, encoding)?)
+}
+
+// Reads DSL content from any source. There is no filename, so there is no extension check either.
+pub fn read_dsl_source<R: Read>(mut source: R, encoding: Option<&str>) -> Result<Vec<String>, HarlawError> {
+    let mut bytes = vec![];
+    source.read_to_end(&mut bytes)?;
+
+    into_lines(decode_bytes_lossy(bytes, encoding)?)
+}
+
+// String is already decoded, only byte order mark may remain.
+pub fn read_dsl_str(content: &str) -> Vec<String> {
+    content
+        .trim_start_matches(UTF8_BOM)
+        .lines()
+        .map(String::from)
+        .collect()
 }
 
 #[cfg(test)]
@@ -40,41 +320,51 @@ mod tests {
         let filename2 = "bar.dsl";
         let filename3 = "foo.json";
         let filename4 = "virus.exe";
+        let filename5 = "README";
+        let filename6 = "foo.dsl.dz";
+        let filename7 = "foo.dsl.gz";
+        let filename8 = "foo.txt.dz";
+        let filename9 = "notes.json.gz";
 
-        let result1 = is_dsl_file(&filename1);
-        let result2 = is_dsl_file(&filename2);
-        let result3 = is_dsl_file(&filename3);
-        let result4 = is_dsl_file(&filename4);
+        let result1 = is_dsl_file(filename1);
+        let result2 = is_dsl_file(filename2);
+        let result3 = is_dsl_file(filename3);
+        let result4 = is_dsl_file(filename4);
 
-        assert_eq!(result1, true);
-        assert_eq!(result2, true);
-        assert_eq!(result3, false);
-        assert_eq!(result4, false);
+        assert!(result1);
+        assert!(result2);
+        assert!(!result3);
+        assert!(!result4);
+        assert!(!is_dsl_file(filename5));
+        assert!(is_dsl_file(filename6));
+        assert!(is_dsl_file(filename7));
+        assert!(!is_dsl_file(filename8));
+        assert!(!is_dsl_file(filename9));
     }
 
     #[test]
     fn errors_on_non_dsl_files() {
         let filename = "undefined.json";
 
-        let result = read_dsl_file(filename);
+        let result = read_dsl_file(filename, None);
 
-        assert_eq!(result, Err("Given file was not a DSL file"));
+        assert!(matches!(result, Err(HarlawError::NotDsl)));
     }
 
     #[test]
     fn errors_on_invalid_dsl_files() {
         let filename = "undefined.dsl";
 
-        let result = read_dsl_file(filename);
+        let result = read_dsl_file(filename, None);
 
-        assert_eq!(result, Err("Could not read the given DSL file"));
+        assert!(matches!(result, Err(HarlawError::Io(_))));
     }
 
     #[test]
     fn reads_dsl_file() {
         let filename = "src/reader/fixtures/dummy.dsl";
 
-        let result = read_dsl_file(filename).unwrap();
+        let result = read_dsl_file(filename, None).unwrap();
 
         assert_eq!(result[0], "#NAME	\"Test Dictionary fixture\"");
         assert_eq!(result[1], "#INDEX_LANGUAGE	\"English\"");
@@ -82,4 +372,232 @@ mod tests {
         assert_eq!(result[3], "foo");
         assert_eq!(result[4], "	[m1]Lorem ipsum dolor sit amet, dolor sit igitur[/m]");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reads_utf8_dsl_file_with_bom() {
+        let filename = "src/reader/fixtures/dummy-utf8-bom.dsl";
+
+        let result = read_dsl_file(filename, None).unwrap();
+
+        assert_eq!(result[0], "#NAME	\"Test Dictionary fixture\"");
+        assert_eq!(result[3], "foo");
+        assert_eq!(result[4], "	[m1]Lorem ipsum dolor sit amet, dolor sit igitur[/m]");
+    }
+
+    #[test]
+    fn reads_utf16le_dsl_file() {
+        let filename = "src/reader/fixtures/dummy-utf16le.dsl";
+
+        let result = read_dsl_file(filename, None).unwrap();
+
+        assert_eq!(result.len(), 5);
+        assert_eq!(result[0], "#NAME	\"Test Dictionary fixture\"");
+        assert_eq!(result[3], "foo");
+        assert_eq!(result[4], "	[m1]Lorem ipsum dolor sit amet, dolor sit igitur[/m]");
+    }
+
+    #[test]
+    fn reads_utf16be_dsl_file() {
+        let filename = "src/reader/fixtures/dummy-utf16be.dsl";
+
+        let result = read_dsl_file(filename, None).unwrap();
+
+        assert_eq!(result.len(), 5);
+        assert_eq!(result[0], "#NAME	\"Test Dictionary fixture\"");
+        assert_eq!(result[3], "foo");
+        assert_eq!(result[4], "	[m1]Lorem ipsum dolor sit amet, dolor sit igitur[/m]");
+    }
+
+    #[test]
+    fn marks_truncated_utf16_content_broken() {
+        let bytes = [0xFF, 0xFE, 0x66, 0x00, 0x6F];
+
+        let result = decode_dsl_bytes(&bytes, None).unwrap();
+
+        assert_eq!(result.lines, vec!["f"]);
+        assert_eq!(result.broken_lines, vec![1]);
+    }
+
+    #[test]
+    fn marks_broken_utf8_lines() {
+        let bytes = b"foo\r\n\xFF\xFE\xFD\n\tbar\n";
+
+        let result = decode_dsl_bytes(bytes, None).unwrap();
+
+        assert_eq!(result.lines, vec!["foo", "\u{FFFD}\u{FFFD}\u{FFFD}", "\tbar"]);
+        assert_eq!(result.broken_lines, vec![2]);
+    }
+
+    #[test]
+    fn marks_broken_utf16_lines() {
+        // "a\nb" followed by unpaired surrogate.
+        let bytes = [0xFF, 0xFE, 0x61, 0x00, 0x0A, 0x00, 0x62, 0x00, 0x00, 0xD8];
+
+        let result = decode_dsl_bytes(&bytes, None).unwrap();
+
+        assert_eq!(result.lines, vec!["a", "b\u{FFFD}"]);
+        assert_eq!(result.broken_lines, vec![2]);
+    }
+
+    #[test]
+    fn errors_on_broken_lines_when_reading_strictly() {
+        let filename = "src/reader/fixtures/dummy-central-european.dsl";
+
+        let result = read_dsl_file(filename, Some("utf-8"));
+
+        assert!(matches!(result, Err(HarlawError::Encoding { line: 1 })));
+    }
+
+    #[test]
+    fn finds_encodings_by_code_page_or_label() {
+        assert_eq!(find_encoding("Cyrillic"), Some(encoding_rs::WINDOWS_1251));
+        assert_eq!(find_encoding("\"EasternEuropean\""), Some(encoding_rs::WINDOWS_1250));
+        assert_eq!(find_encoding(" latin"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(find_encoding("cp1251"), Some(encoding_rs::WINDOWS_1251));
+        assert_eq!(find_encoding("Klingon"), None);
+    }
+
+    #[test]
+    fn reads_dsl_file_in_declared_code_page() {
+        let filename = "src/reader/fixtures/dummy-cyrillic.dsl";
+
+        let result = read_dsl_file(filename, None).unwrap();
+
+        assert_eq!(result[0], "#NAME\t\"Тестовый словарь\"");
+        assert_eq!(result[4], "кот");
+        assert_eq!(result[5], "\t[m1]домашнее животное[/m]");
+    }
+
+    #[test]
+    fn prefers_utf8_content_over_declared_code_page() {
+        let bytes = "#SOURCE_CODE_PAGE\tLatin\nþorn\n\t[m1]rune[/m]".as_bytes();
+
+        let result = decode_dsl_bytes(bytes, None).unwrap();
+
+        assert_eq!(result.lines[1], "þorn");
+    }
+
+    #[test]
+    fn reads_dsl_file_with_explicit_encoding() {
+        let filename = "src/reader/fixtures/dummy-central-european.dsl";
+
+        let result = read_dsl_file(filename, Some("windows-1250")).unwrap();
+
+        assert_eq!(result[3], "łódź");
+        assert_eq!(result[4], "\t[m1]čeština, słowo[/m]");
+    }
+
+    #[test]
+    fn errors_on_unknown_explicit_encoding() {
+        let filename = "src/reader/fixtures/dummy.dsl";
+
+        let result = read_dsl_file(filename, Some("Klingon"));
+
+        assert!(matches!(result, Err(HarlawError::UnsupportedEncoding(label)) if label == "Klingon"));
+    }
+
+    #[test]
+    fn streams_utf16_dsl_file_as_utf8() {
+        let filename = "src/reader/fixtures/dummy-utf16le.dsl";
+
+        let lines: Vec<String> = open_dsl_stream(filename, None).unwrap()
+            .lines()
+            .map(|line| line.unwrap())
+            .collect();
+
+        assert_eq!(lines[0], "#NAME	\"Test Dictionary fixture\"");
+        assert_eq!(lines[3], "foo");
+    }
+
+    #[test]
+    fn reads_only_headers_of_dsl_file() {
+        let filename = "src/reader/fixtures/dummy-utf16le.dsl";
+
+        let result = read_dsl_headers(filename, None).unwrap();
+
+        assert_eq!(result, vec![
+            "#NAME	\"Test Dictionary fixture\"",
+            "#INDEX_LANGUAGE	\"English\"",
+            "#CONTENTS_LANGUAGE	\"Latin\"",
+        ]);
+    }
+
+    #[test]
+    fn streams_dsl_file_in_declared_code_page() {
+        let filename = "src/reader/fixtures/dummy-cyrillic.dsl";
+
+        let lines: Vec<String> = open_dsl_stream(filename, None).unwrap()
+            .lines()
+            .map(|line| line.unwrap())
+            .collect();
+
+        assert_eq!(lines[4], "кот");
+    }
+
+    #[test]
+    fn reads_dsl_source_without_extension_check() {
+        let source = fs::File::open("src/reader/fixtures/dummy-utf16be.dsl").unwrap();
+
+        let result = read_dsl_source(source, None).unwrap();
+
+        assert_eq!(result[3], "foo");
+    }
+
+    #[test]
+    fn reads_dsl_str() {
+        let result = read_dsl_str("\u{FEFF}#NAME\t\"Test\"\r\nfoo\r\n\t[m1]bar[/m]");
+
+        assert_eq!(result, vec!["#NAME\t\"Test\"", "foo", "\t[m1]bar[/m]"]);
+    }
+
+    #[test]
+    fn reads_dictzip_dsl_file() {
+        let filename = "src/reader/fixtures/dummy.dsl.dz";
+
+        let result = read_dsl_file(filename, None).unwrap();
+
+        assert_eq!(result[0], "#NAME	\"Test Dictionary fixture\"");
+        assert_eq!(result[3], "foo");
+        assert_eq!(result[4], "	[m1]Lorem ipsum dolor sit amet, dolor sit igitur[/m]");
+    }
+
+    #[test]
+    fn reads_gzip_dsl_file() {
+        let filename = "src/reader/fixtures/dummy.dsl.gz";
+
+        let result = read_dsl_file(filename, None).unwrap();
+
+        assert_eq!(result[3], "foo");
+    }
+
+    #[test]
+    fn reads_compressed_dsl_source() {
+        let source = fs::File::open("src/reader/fixtures/dummy.dsl.gz").unwrap();
+
+        let result = read_dsl_source(source, None).unwrap();
+
+        assert_eq!(result[3], "foo");
+    }
+
+    #[test]
+    fn streams_dictzip_dsl_file() {
+        let filename = "src/reader/fixtures/dummy.dsl.dz";
+
+        let lines: Vec<String> = open_dsl_stream(filename, None).unwrap()
+            .lines()
+            .map(|line| line.unwrap())
+            .collect();
+
+        assert_eq!(lines[0], "#NAME	\"Test Dictionary fixture\"");
+        assert_eq!(lines[3], "foo");
+    }
+
+    #[test]
+    fn errors_on_corrupted_compressed_file() {
+        let bytes = vec![0x1F, 0x8B, 0x08, 0x00, 0x00];
+
+        let result = decompress(bytes);
+
+        assert!(matches!(result, Err(HarlawError::Io(_))));
+    }
+}
@@ -1,12 +1,13 @@
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 
 pub const TAB: &str = "\t";
 pub const SPACE: &char = &' ';
 pub const SKIPS: &[&str]= &["#"];
+pub const INCLUDE: &str = "#INCLUDE";
 
 const MARGINS: &[&str] = &["[/m]", "[m0]", "[m1]", "[m2]", "[m3]", "[m4]", "[m5]", "[m6]", "[m7]", "[m8]", "[m9]", "[m10]"];
-const COLORS: &[&str] = &["[c aliceblue]", "[c antiquewhite]", "[c aqua]", "[c aquamarine]", "[c azure]", "[c beige]", "[c bisque]", "[c blanchedalmond]", "[c blue]", "[c blueviolet]", "[c brown]", "[c burlywood]", "[c cadetblue]", "[c chartreuse]", "[c chocolate]", "[c coral]", "[c cornflowerblue]", "[c cornsilk]", "[c crimson]", "[c cyan]", "[c darkblue]", "[c darkcyan]", "[c darkgoldenrod]", "[c darkgray]", "[c darkgreen]", "[c darkkhaki]", "[c darkmagenta]", "[c darkolivegreen]", "[c darkorange]", "[c darkorchid]", "[c darkred]", "[c darksalmon]", "[c darkseagreen]", "[c darkslateblue]", "[c darkslategray]", "[c darkturquoise]", "[c darkviolet]", "[c deeppink]", "[c deepskyblue]", "[c dimgray]", "[c dodgerblue]", "[c firebrick]", "[c floralwhite]", "[c forestgreen]", "[c fuchsia]", "[c gainsboro]", "[c ghostwhite]", "[c gold]", "[c goldenrod]", "[c gray]", "[c green]", "[c greenyellow]", "[c honeydew]", "[c hotpink]", "[c indianred]", "[c indigo]", "[c ivory]", "[c khaki]", "[c lavender]", "[c lavenderblush]", "[c lawngreen]", "[c lemonchiffon]", "[c lightblue]", "[c lightcoral]", "[c lightcyan]", "[c lightgoldenrodyellow]", "[c lightgreen]", "[c lightgrey]", "[c lightpink]", "[c lightsalmon]", "[c lightseagreen]", "[c lightskyblue]", "[c lightslategray]", "[c lightsteelblue]", "[c lightyellow]", "[c lime]", "[c limegreen]", "[c linen]", "[c magenta]", "[c maroon]", "[c mediumaquamarine]", "[c mediumblue]", "[c mediumorchid]", "[c mediumpurple]", "[c mediumseagreen]", "[c mediumslateblue]", "[c mediumspringgreen]", "[c mediumturquoise]", "[c mediumvioletred]", "[c midnightblue]", "[c mintcream]", "[c mistyrose]", "[c moccasin]", "[c navajowhite]", "[c navy]", "[c oldlace]", "[c olive]", "[c olivedrab]", "[c orange]", "[c orangered]", "[c orchid]", "[c palegoldenrod]", "[c palegreen]", "[c paleturquoise]", "[c palevioletred]", "[c papayawhip]", "[c peachpuff]", "[c peru]", "[c pink]", "[c plum]", "[c powderblue]", "[c purple]", "[c red]", "[c rosybrown]", "[c royalblue]", "[c saddlebrown]", "[c salmon]", "[c sandybrown]", "[c seagreen]", "[c seashell]", "[c sienna]", "[c silver]", "[c skyblue]", "[c slateblue]", "[c slategray]", "[c snow]", "[c springgreen]", "[c steelblue]", "[c tan]", "[c teal]", "[c thistle]", "[c tomato]", "[c turquoise]", "[c violet]", "[c wheat]", "[c white]", "[c whitesmoke]", "[c yellow]", "[c yellowgreen]", "[/c]"];
-const COMMON: &[&str] = &["[u]", "[/u]", "[trn]", "[/trn]", "[!trs]", "[/!trs]", "[com]", "[/com]", "[s]", "[/s]", "[lang]", "[/lang]", "[ex]", "[/ex]"];
+const COMMON: &[&str] = &["[u]", "[/u]", "[trn]", "[/trn]", "[!trs]", "[/!trs]", "[com]", "[/com]", "[ex]", "[/ex]"];
 const REPLACEABLES: &[&str] = &["[b]", "[/b]", "[i]", "[/i]", "[p]", "[/p]", "[ref]", "[/ref]", "[sub]", "[/sub]", "[sup]", "[/sup]"];
 
 /// Search/replace struct for custom settings.
@@ -23,12 +24,111 @@ const REPLACEABLES: &[&str] = &["[b]", "[/b]", "[i]", "[/i]", "[p]", "[/p]", "[r
 /// 
 /// ```
 ///
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ContentReplace<'a> {
     pub search: &'a str,
     pub replace: &'a str,
 }
 
+/// Transform for `[c color]` tags, which may have any color name.
+///
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_default_settings, ColorMarkup, HarlawSettings};
+/// 
+/// // [c red]foo[/c] -> <span class="color-red">foo</span>
+/// let settings = HarlawSettings {
+///     colors: ColorMarkup::Class("color-"),
+///     ..get_default_settings()
+/// };
+/// 
+/// ```
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum ColorMarkup<'a> {
+    /// Color tags are left as they are, for removes and replaces.
+    #[default]
+    Keep,
+    /// Color tags are removed, keeping their content.
+    Remove,
+    /// `<span style="color:red">`
+    Style,
+    /// `<span class="{prefix}red">`, with the given class prefix.
+    Class(&'a str),
+}
+
+/// Transform for `[lang]` tags, which tell the language of their content by Lingvo id or name.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum LanguageMarkup {
+    /// Language tags are left as they are, for removes and replaces.
+    #[default]
+    Keep,
+    /// Language tags are removed, keeping their content.
+    Remove,
+    /// `<span lang="de">`, with BCP-47 code of the language. Tags of unknown languages are removed.
+    Span,
+}
+
+/// Base URLs for media files in HTML, by kind of media.
+/// File names are appended to these, so they usually end with slash.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub struct MediaUrls<'a> {
+    pub sound: &'a str,
+    pub image: &'a str,
+    pub video: &'a str,
+    pub other: &'a str,
+}
+
+/// Transform for `[s]` tags, which refer to media files of the dictionary.
+///
+/// # Examples
+/// 
+/// ```
+/// use harlaw::{get_default_settings, HarlawSettings, MediaMarkup, MediaUrls};
+/// 
+/// // [s]hus.wav[/s] -> <audio controls src="https://example.com/sounds/hus.wav"></audio>
+/// let settings = HarlawSettings {
+///     media: MediaMarkup::Html(MediaUrls {
+///         sound: "https://example.com/sounds/",
+///         image: "https://example.com/images/",
+///         ..Default::default()
+///     }),
+///     ..get_default_settings()
+/// };
+/// 
+/// ```
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum MediaMarkup<'a> {
+    /// Media tags are left as they are, for removes and replaces.
+    #[default]
+    Keep,
+    /// Media tags are removed along with the file names.
+    Remove,
+    /// `<audio>`, `<img>` and `<video>` elements, or links for other files.
+    #[serde(borrow)]
+    Html(MediaUrls<'a>),
+}
+
+/// Policy for `[*]` secondary zones, to produce both compact and full versions of dictionary.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum SecondaryZones {
+    /// Secondary zone tags are left as they are, for removes and replaces.
+    #[default]
+    Keep,
+    /// Secondary zones are dropped along with their content, for compact version.
+    /// Definitions left empty are dropped as well.
+    Drop,
+    /// Secondary zone tags are removed, keeping their content inline.
+    Inline,
+    /// Secondary zones are wrapped in `<span class="secondary">`.
+    Wrap,
+}
+
 /// Custom settings object for running removes and
 /// search/replaces on DSL file.
 ///
@@ -57,15 +157,51 @@ pub struct ContentReplace<'a> {
 ///         replace: "</skew>",
 ///     }
 /// ],
+/// encoding: Some("windows-1251"),
+/// substitute_tilde: true,
+/// ..Default::default()
 /// };
 /// 
 /// ```
 ///
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct HarlawSettings<'a> {
     #[serde(borrow)]
     pub replaces: Vec<ContentReplace<'a>>,    
     pub removes: Vec<&'a str>,
+    /// Encoding to read the DSL file with, eg. "windows-1251" or Lingvo code page name "Cyrillic".
+    /// When not set, encoding is detected from byte order mark or #SOURCE_CODE_PAGE header.
+    #[serde(default)]
+    pub encoding: Option<&'a str>,
+    /// Replace unescaped `~` in definitions with the headword of the entry, and `^~` with capitalised headword.
+    #[serde(default)]
+    pub substitute_tilde: bool,
+    /// Extract `[trn]`, `[!trs]`, `[ex]` and `[com]` zones to translations, examples and comments of the entry.
+    /// Definitions are formatted as usual.
+    #[serde(default)]
+    pub extract_zones: bool,
+    /// Expansions of `[p]` labels, eg. "f." -> "feminine". Shown as title of the label in HTML.
+    /// File based methods load these from the `*_abrv.dsl` file next to dictionary, when not set.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub abbreviations: BTreeMap<String, String>,
+    /// How `[c color]` tags are transformed. Bare `[c]` is green, like in Lingvo.
+    #[serde(default, borrow)]
+    pub colors: ColorMarkup<'a>,
+    /// How `[lang id=1031]` and `[lang name="German"]` tags are transformed.
+    #[serde(default)]
+    pub languages: LanguageMarkup,
+    /// How `[s]` media tags are transformed, along with the file names inside them.
+    #[serde(default, borrow)]
+    pub media: MediaMarkup<'a>,
+    /// How `[*]` secondary zones are handled. They are shown only in the full card view of Lingvo.
+    #[serde(default)]
+    pub secondary: SecondaryZones,
+    /// Keep `{{comments}}` as notes of the entry. Comments are never shown in headwords or definitions.
+    #[serde(default)]
+    pub keep_notes: bool,
+    /// Add `@` subentries as entries of their own, right after their main entry, instead of nesting them.
+    #[serde(default)]
+    pub flatten_subentries: bool,
 }
 
 fn get_replaces() -> Vec<ContentReplace<'static>> {
@@ -122,8 +258,18 @@ fn get_replaces() -> Vec<ContentReplace<'static>> {
 ///
 pub fn get_default_settings() -> HarlawSettings<'static> {
     HarlawSettings {
-        removes: [MARGINS, COLORS, &[TAB], COMMON].concat(),
+        removes: [MARGINS, &[TAB], COMMON].concat(),
         replaces: get_replaces(),
+        encoding: None,
+        substitute_tilde: false,
+        extract_zones: false,
+        abbreviations: BTreeMap::new(),
+        colors: ColorMarkup::Style,
+        languages: LanguageMarkup::Span,
+        media: MediaMarkup::Html(MediaUrls::default()),
+        secondary: SecondaryZones::Wrap,
+        keep_notes: false,
+        flatten_subentries: false,
     }
 }
 
@@ -157,7 +303,17 @@ pub fn get_default_settings() -> HarlawSettings<'static> {
 ///
 pub fn get_no_markup_settings() -> HarlawSettings<'static> {
     HarlawSettings {
-        removes: [MARGINS, COLORS, &[TAB], COMMON, REPLACEABLES].concat(),
+        removes: [MARGINS, &[TAB], COMMON, REPLACEABLES].concat(),
         replaces: vec![],
+        encoding: None,
+        substitute_tilde: false,
+        extract_zones: false,
+        abbreviations: BTreeMap::new(),
+        colors: ColorMarkup::Remove,
+        languages: LanguageMarkup::Remove,
+        media: MediaMarkup::Remove,
+        secondary: SecondaryZones::Inline,
+        keep_notes: false,
+        flatten_subentries: false,
     }
 }
\ No newline at end of file
@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+use crate::formatter::{parse_include_line, CardParser};
+use crate::settings::HarlawSettings;
+use crate::{DictionaryEntry, HarlawError};
+
+const UTF8_BOM: char = '\u{FEFF}';
+
+// Maximum number of headwords waiting for their shared definitions, to keep lookahead bounded.
+const MAX_CARD_HEADWORDS: usize = 256;
+
+/// Iterator reading DSL entries lazily from any `BufRead` source, one card at a time.
+/// Memory use depends on the size of a single card, not the whole dictionary.
+///
+/// Source should be UTF-8. Problems are yielded as errors, after which
+/// iteration can continue with the next entries. A group of more than 256 headwords
+/// without definitions is taken as broken, and its headwords are yielded as errors.
+/// `#INCLUDE` directives cannot be resolved from a stream, so they are yielded as errors too.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use harlaw::{EntryIter, get_default_settings};
+///
+/// let source = Cursor::new("foo\n\t[m1][b]bar[/b][/m]\n");
+///
+/// for entry in EntryIter::new(source, get_default_settings()) {
+///     let entry = entry.unwrap();
+///     assert_eq!(entry.word, "foo");
+///     assert_eq!(entry.definitions[0].text, "<strong>bar</strong>");
+/// }
+/// ```
+///
+pub struct EntryIter<'a, R: BufRead> {
+    reader: R,
+    parser: CardParser<'a>,
+    line_number: usize,
+    buffer: Vec<u8>,
+    finished: bool,
+    pending_errors: VecDeque<HarlawError>,
+}
+
+impl<'a, R: BufRead> EntryIter<'a, R> {
+    pub fn new(reader: R, settings: HarlawSettings<'a>) -> Self {
+        EntryIter {
+            reader,
+            parser: CardParser::new(settings),
+            line_number: 0,
+            buffer: vec![],
+            finished: false,
+            pending_errors: VecDeque::new(),
+        }
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+
+        let diagnostics = self.parser.finish();
+        self.pending_errors.extend(diagnostics.into_iter().map(HarlawError::from));
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>, HarlawError> {
+        self.buffer.clear();
+
+        if self.reader.read_until(b'\n', &mut self.buffer)? == 0 {
+            return Ok(None);
+        }
+
+        self.line_number += 1;
+
+        let raw_line = self.buffer.strip_suffix(b"\n").unwrap_or(&self.buffer);
+        let raw_line = raw_line.strip_suffix(b"\r").unwrap_or(raw_line);
+
+        match std::str::from_utf8(raw_line) {
+            Ok(line) if self.line_number == 1 => Ok(Some(line.trim_start_matches(UTF8_BOM).to_string())),
+            Ok(line) => Ok(Some(line.to_string())),
+            Err(_e) => Err(HarlawError::Encoding { line: self.line_number }),
+        }
+    }
+}
+
+impl<'a, R: BufRead> Iterator for EntryIter<'a, R> {
+    type Item = Result<DictionaryEntry, HarlawError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.parser.next_entry() {
+                return Some(Ok(entry));
+            }
+
+            if let Some(error) = self.pending_errors.pop_front() {
+                return Some(Err(error));
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            match self.read_line() {
+                Ok(Some(line)) => {
+                    // Like in files, the card before the directive is complete.
+                    if parse_include_line(&line).is_some() && !self.parser.in_comment() {
+                        let diagnostics = self.parser.finish();
+                        self.pending_errors.extend(diagnostics.into_iter().map(HarlawError::from));
+                        self.pending_errors.push_back(HarlawError::UnresolvedInclude { line: self.line_number });
+                        continue;
+                    }
+
+                    if let Err(diagnostic) = self.parser.push_line(self.line_number, &line) {
+                        return Some(Err(diagnostic.into()));
+                    }
+
+                    if self.parser.pending_headwords() > MAX_CARD_HEADWORDS {
+                        let dropped = self.parser.drop_pending_headwords();
+                        self.pending_errors.extend(dropped.into_iter().map(HarlawError::from));
+                    }
+                },
+                Ok(None) => self.finish(),
+                Err(HarlawError::Io(error)) => {
+                    self.finished = true;
+                    return Some(Err(HarlawError::Io(error)));
+                },
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::settings::get_default_settings;
+    use crate::Definition;
+
+    fn texts(definitions: &[Definition]) -> Vec<&str> {
+        definitions.iter().map(|definition| definition.text.as_str()).collect()
+    }
+
+    #[test]
+    fn iterates_entries_from_reader() {
+        let source = Cursor::new("#NAME\t\"Test\"\r\nfoo\r\n\t[m1]Lorem [i]ipsum[/i][/m]\r\nbar\r\nbaz\r\n\t[m1]Dolor[/m]\r\n\t[m2]Sit amet[/m]\r\n");
+
+        let entries: Vec<DictionaryEntry> = EntryIter::new(source, get_default_settings())
+            .map(|entry| entry.unwrap())
+            .collect();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].word, "foo");
+        assert_eq!(texts(&entries[0].definitions), vec!["Lorem <i>ipsum</i>"]);
+        assert_eq!(entries[1].word, "bar");
+        assert_eq!(texts(&entries[1].definitions), vec!["Dolor", "Sit amet"]);
+        assert_eq!(entries[2].word, "baz");
+        assert_eq!(texts(&entries[2].definitions), vec!["Dolor", "Sit amet"]);
+    }
+
+    #[test]
+    fn yields_entries_before_reading_whole_source() {
+        let source = Cursor::new("foo\n\t[m1]Lorem[/m]\nbar\n\t[m1]Ipsum[/m]\n");
+        let mut entries = EntryIter::new(source, get_default_settings());
+
+        let first = entries.next().unwrap().unwrap();
+
+        assert_eq!(first.word, "foo");
+        assert_eq!(entries.line_number, 3);
+    }
+
+    #[test]
+    fn continues_after_errors() {
+        let source = Cursor::new(b"\t[m1]Orphan[/m]\nfoo\n\t[m1]Lorem \xFF[/m]\n\t[m1]Ipsum[/m]\nbar\n".to_vec());
+
+        let results: Vec<Result<DictionaryEntry, HarlawError>> = EntryIter::new(source, get_default_settings()).collect();
+
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[0], Err(HarlawError::OrphanDefinition { line: 1 })));
+        assert!(matches!(results[1], Err(HarlawError::Encoding { line: 3 })));
+        assert!(matches!(&results[2], Ok(entry) if entry.word == "foo" && texts(&entry.definitions) == vec!["Ipsum"]));
+        assert!(matches!(&results[3], Err(HarlawError::MissingDefinition { headword, line: 5 }) if headword == "bar"));
+    }
+
+    #[test]
+    fn reports_each_headword_over_lookahead_limit() {
+        let mut content: String = (0..=MAX_CARD_HEADWORDS).map(|index| format!("word{}\n", index)).collect();
+        content.push_str("\t[m1]Lorem ipsum[/m]\n");
+
+        let results: Vec<Result<DictionaryEntry, HarlawError>> = EntryIter::new(Cursor::new(content), get_default_settings()).collect();
+
+        assert_eq!(results.len(), MAX_CARD_HEADWORDS + 1);
+        assert!(matches!(&results[0], Err(HarlawError::MissingDefinition { headword, line: 1 }) if headword == "word0"));
+        assert!(matches!(&results[MAX_CARD_HEADWORDS - 1], Err(HarlawError::MissingDefinition { line, .. }) if *line == MAX_CARD_HEADWORDS));
+        assert!(matches!(&results[MAX_CARD_HEADWORDS], Ok(entry) if entry.word == format!("word{}", MAX_CARD_HEADWORDS)));
+    }
+
+    #[test]
+    fn yields_error_for_include() {
+        let source = Cursor::new("foo\n\t[m1]Lorem[/m]\n#INCLUDE \"part2.dsl\"\nbar\n\t[m1]Ipsum[/m]\n");
+
+        let results: Vec<Result<DictionaryEntry, HarlawError>> = EntryIter::new(source, get_default_settings()).collect();
+
+        assert_eq!(results.len(), 3);
+        assert!(matches!(&results[0], Ok(entry) if entry.word == "foo"));
+        assert!(matches!(results[1], Err(HarlawError::UnresolvedInclude { line: 3 })));
+        assert!(matches!(&results[2], Ok(entry) if entry.word == "bar"));
+    }
+
+    #[test]
+    fn skips_byte_order_mark() {
+        let source = Cursor::new("\u{FEFF}foo\n\t[m1]Lorem[/m]\n");
+
+        let entry = EntryIter::new(source, get_default_settings()).next().unwrap().unwrap();
+
+        assert_eq!(entry.word, "foo");
+    }
+}
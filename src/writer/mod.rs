@@ -1,20 +1,24 @@
 use std::fs;
-use crate::DictionaryEntry;
+use serde::Serialize;
+use crate::{Dictionary, DictionaryEntry, HarlawError};
 
-fn json_stringify(entries: &[DictionaryEntry]) -> Result<String, &'static str> {
-    match serde_json::to_string(&entries) {
-        Ok(json) => Ok(json),
-        Err(_e) => Err("Could not stringify entries"),
-    }
+fn json_stringify<T: Serialize + ?Sized>(content: &T) -> Result<String, HarlawError> {
+    Ok(serde_json::to_string(content)?)
 }
 
-pub fn write_entries_to_json(path: &str, entries: &[DictionaryEntry]) -> Result<(), &'static str> {
-    let json =  json_stringify(entries).unwrap_or_default();
+fn write_json<T: Serialize + ?Sized>(path: &str, content: &T) -> Result<(), HarlawError> {
+    let json = json_stringify(content)?;
 
-    match fs::write(path, json) {
-        Ok(_res) => Ok(()),
-        Err(_e) => Err("Could not write JSON file")
-    }
+    Ok(fs::write(path, json)?)
+}
+
+pub fn write_entries_to_json(path: &str, entries: &[DictionaryEntry]) -> Result<(), HarlawError> {
+    write_json(path, entries)
+}
+
+// Metadata envelope: {"metadata": {...}, "entries": [...]}
+pub fn write_dictionary_to_json(path: &str, dictionary: &Dictionary) -> Result<(), HarlawError> {
+    write_json(path, dictionary)
 }
 
 
@@ -22,39 +26,69 @@ pub fn write_entries_to_json(path: &str, entries: &[DictionaryEntry]) -> Result<
 mod tests {
     use super::*;
     use std::path::Path;
+    use crate::{Definition, DictionaryMetadata};
 
     #[test]
     fn stringifies_entries() {
         let entry = DictionaryEntry {
             word: String::from("Foo"),
-            definitions: vec![String::from("Bar baz")]
+            definitions: vec![Definition { level: 1, text: String::from("Bar baz") }],
+            ..Default::default()
         };
         let entries = vec![entry];
 
         let result = json_stringify(&entries).unwrap();
-        let expected = "[{\"word\":\"Foo\",\"definitions\":[\"Bar baz\"]}]";
+        let expected = "[{\"word\":\"Foo\",\"definitions\":[{\"level\":1,\"text\":\"Bar baz\"}]}]";
 
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn stringifies_dictionary_with_metadata() {
+        let dictionary = Dictionary {
+            metadata: DictionaryMetadata {
+                name: Some(String::from("Foo")),
+                ..Default::default()
+            },
+            entries: vec![DictionaryEntry {
+                word: String::from("Foo"),
+                definitions: vec![Definition { level: 1, text: String::from("Bar baz") }],
+                ..Default::default()
+            }],
+        };
+
+        let result = json_stringify(&dictionary).unwrap();
+        let expected = "{\"metadata\":{\"name\":\"Foo\",\"index_language\":null,\"contents_language\":null,\"source_code_page\":null,\"extra\":{},\"annotation\":null,\"icon\":null},\"entries\":[{\"word\":\"Foo\",\"definitions\":[{\"level\":1,\"text\":\"Bar baz\"}]}]}";
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn errors_on_unwritable_path() {
+        let result = write_entries_to_json("./src/writer/missing-directory/test.json", &[]);
+
+        assert!(matches!(result, Err(HarlawError::Io(_))));
+    }
+
     #[test]
     fn writes_entries_to_json_file() {
         let entry = DictionaryEntry {
             word: String::from("Foo"),
-            definitions: vec![String::from("Bar baz")]
+            definitions: vec![Definition { level: 1, text: String::from("Bar baz") }],
+            ..Default::default()
         };
         let entries = vec![entry];
         let path = "./src/writer/test.json";
 
         // Ensure JSON file does not already exist.
         let path_exists = Path::new(&path).exists();
-        assert_eq!(path_exists, false);
+        assert!(!path_exists);
 
-        write_entries_to_json(&path, &entries).unwrap();
+        write_entries_to_json(path, &entries).unwrap();
         
         // Assert file has now been created.
         let json_exists = Path::new(&path).exists();
-        assert_eq!(json_exists, true);
+        assert!(json_exists);
 
         // Clean up created file.
         if json_exists {
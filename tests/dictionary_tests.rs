@@ -1,5 +1,6 @@
-use harlaw::{get_dictionary, get_dictionary_without_markup, get_dictionary_with_custom_settings};
-use harlaw::{HarlawSettings, ContentReplace};
+use harlaw::{get_dictionary, get_dictionary_without_markup, get_dictionary_with_custom_settings, get_dictionary_with_metadata, get_dictionary_iter};
+use harlaw::DictionaryEntry;
+use harlaw::{HarlawSettings, ContentReplace, get_default_settings, get_no_markup_settings};
 use insta::assert_json_snapshot;
 
 #[test]
@@ -49,8 +50,55 @@ fn gets_dictionary_with_custom_settings() {
                 replace: "</VINO>",
             }
         ],
+        ..Default::default()
     };
     let result = get_dictionary_with_custom_settings(input, settings).unwrap();
 
     assert_json_snapshot!(result)
-}
\ No newline at end of file
+}
+
+#[test]
+fn gets_dictionary_in_declared_code_page() {
+    let input = "./tests/fixtures/cyrillic_dictionary.dsl";
+    let result = get_dictionary(input).unwrap();
+
+    assert_eq!(result[0].word, "кот");
+    assert_eq!(result[0].definitions[0].text, "<i>n.</i> cat");
+    assert_eq!(result[1].word, "собака");
+}
+
+#[test]
+fn gets_dictionary_with_explicit_encoding() {
+    let input = "./tests/fixtures/cyrillic_dictionary.dsl";
+    let settings = HarlawSettings {
+        encoding: Some("windows-1252"),
+        ..get_no_markup_settings()
+    };
+    let result = get_dictionary_with_custom_settings(input, settings).unwrap();
+
+    // Explicit encoding wins over the declared code page.
+    assert_eq!(result[0].word, "êîò");
+    assert_eq!(result[0].definitions[0].text, "n. cat");
+}
+
+#[test]
+fn gets_dictionary_with_metadata() {
+    let input = "./tests/fixtures/default_dictionary.dsl";
+    let result = get_dictionary_with_metadata(input, get_default_settings()).unwrap();
+
+    assert_json_snapshot!(result)
+}
+
+#[test]
+fn iterates_same_entries_as_default_dictionary() {
+    let input = "./tests/fixtures/default_dictionary_spaces.dsl";
+    let entries: Vec<DictionaryEntry> = get_dictionary_iter(input, get_default_settings())
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect();
+
+    assert_eq!(
+        serde_json::to_string(&entries).unwrap(),
+        serde_json::to_string(&get_dictionary(input).unwrap()).unwrap()
+    );
+}
@@ -1,7 +1,7 @@
 use std::path::Path;
 use std::fs;
-use harlaw::{to_json, to_json_no_markup, to_json_with_custom_settings};
-use harlaw::{HarlawSettings, ContentReplace};
+use harlaw::{to_json, to_json_no_markup, to_json_with_custom_settings, to_json_with_metadata};
+use harlaw::{HarlawSettings, ContentReplace, get_default_settings};
 
 #[test]
 fn saves_default_dictionary_to_json() {
@@ -10,15 +10,15 @@ fn saves_default_dictionary_to_json() {
 
     // Ensure JSON file does not already exist.
     let path_exists = Path::new(&output).exists();
-    assert_eq!(path_exists, false);
+    assert!(!path_exists);
 
     let result = to_json(input, output);
 
-    assert!(!result.is_err());
+    assert!(result.is_ok());
 
     // Assert file has now been created.
     let json_exists = Path::new(&output).exists();
-    assert_eq!(json_exists, true);
+    assert!(json_exists);
 
     // Clean up created file.
     if json_exists {
@@ -33,15 +33,15 @@ fn saves_no_markup_dictionary_to_json() {
 
     // Ensure JSON file does not already exist.
     let path_exists = Path::new(&output).exists();
-    assert_eq!(path_exists, false);
+    assert!(!path_exists);
 
     let result = to_json_no_markup(input, output);
 
-    assert!(!result.is_err());
+    assert!(result.is_ok());
 
     // Assert file has now been created.
     let json_exists = Path::new(&output).exists();
-    assert_eq!(json_exists, true);
+    assert!(json_exists);
 
     // Clean up created file.
     if json_exists {
@@ -73,22 +73,44 @@ fn saves_custom_settings_dictionary_to_json() {
                 replace: "</VINO>",
             }
         ],
+        ..Default::default()
     };
 
     // Ensure JSON file does not already exist.
     let path_exists = Path::new(&output).exists();
-    assert_eq!(path_exists, false);
+    assert!(!path_exists);
 
     let result = to_json_with_custom_settings(input, output, settings);
 
-    assert!(!result.is_err());
+    assert!(result.is_ok());
 
     // Assert file has now been created.
     let json_exists = Path::new(&output).exists();
-    assert_eq!(json_exists, true);
+    assert!(json_exists);
 
     // Clean up created file.
     if json_exists {
         fs::remove_file(output).unwrap();
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn saves_dictionary_with_metadata_to_json() {
+    let input = "./tests/fixtures/default_dictionary.dsl";
+    let output = "./tests/fixtures/default_dictionary_4.json";
+
+    // Ensure JSON file does not already exist.
+    let path_exists = Path::new(&output).exists();
+    assert!(!path_exists);
+
+    let result = to_json_with_metadata(input, output, get_default_settings());
+
+    assert!(result.is_ok());
+
+    // Assert file contains metadata envelope.
+    let json = fs::read_to_string(output).unwrap();
+    assert!(json.starts_with("{\"metadata\":{\"name\":\"Test Dictionary for testing purposes\""));
+
+    // Clean up created file.
+    fs::remove_file(output).unwrap();
+}